@@ -0,0 +1,256 @@
+use std::ops::Range;
+
+use crate::CaseMode;
+use crate::merge_ranges;
+
+const SCORE_MATCH: i64 = 16;
+const SCORE_GAP_START: i64 = -3;
+const SCORE_GAP_EXTENSION: i64 = -1;
+/// Bonus for matching the first character of a word (after a non-word char)
+const BONUS_BOUNDARY: i64 = SCORE_MATCH / 2;
+/// Bonus for matching the first character after whitespace
+const BONUS_BOUNDARY_WHITE: i64 = BONUS_BOUNDARY + 2;
+/// Bonus for matching the first character after a delimiter like `/` or `:`
+const BONUS_BOUNDARY_DELIMITER: i64 = BONUS_BOUNDARY + 1;
+/// Bonus for matching a non-word character
+const BONUS_NON_WORD: i64 = SCORE_MATCH / 2;
+/// Bonus for a camelCase or letter-to-number transition
+const BONUS_CAMEL_123: i64 = BONUS_BOUNDARY - 1;
+/// Minimum bonus for each character of a run of consecutive matches
+const BONUS_CONSECUTIVE: i64 = -(SCORE_GAP_START + SCORE_GAP_EXTENSION);
+/// The bonus of the first pattern character is multiplied by this
+const BONUS_FIRST_CHAR_MULTIPLIER: i64 = 2;
+
+/// The result of a successful fuzzy match
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    /// The quality of the match. Higher is better
+    pub score: i64,
+    /// Byte offsets of the matched characters in the text
+    pub positions: Vec<usize>,
+}
+
+impl FuzzyMatch {
+    /// Convert the matched positions into sorted, non-overlapping byte ranges of
+    /// `text` (the text that was matched)
+    pub fn ranges(&self, text: &str) -> Vec<Range<usize>> {
+        merge_ranges(
+            self.positions
+                .iter()
+                .map(|&pos| {
+                    let len = text[pos..].chars().next().map_or(0, char::len_utf8);
+                    pos..pos + len
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum CharClass {
+    White,
+    Delimiter,
+    NonWord,
+    Lower,
+    Upper,
+    Number,
+}
+
+impl CharClass {
+    fn of(c: char) -> Self {
+        if c.is_whitespace() {
+            CharClass::White
+        } else if matches!(c, '/' | ',' | ':' | ';' | '|') {
+            CharClass::Delimiter
+        } else if c.is_lowercase() {
+            CharClass::Lower
+        } else if c.is_uppercase() {
+            CharClass::Upper
+        } else if c.is_numeric() {
+            CharClass::Number
+        } else if c.is_alphabetic() {
+            // letters without case
+            CharClass::Lower
+        } else {
+            CharClass::NonWord
+        }
+    }
+
+    fn is_word(self) -> bool {
+        matches!(
+            self,
+            CharClass::Lower | CharClass::Upper | CharClass::Number
+        )
+    }
+}
+
+/// The bonus for matching a character of class `cur` that follows a character
+/// of class `prev`
+fn bonus_for(prev: CharClass, cur: CharClass) -> i64 {
+    if cur.is_word() {
+        match prev {
+            CharClass::White => BONUS_BOUNDARY_WHITE,
+            CharClass::Delimiter => BONUS_BOUNDARY_DELIMITER,
+            CharClass::NonWord => BONUS_BOUNDARY,
+            CharClass::Lower if cur == CharClass::Upper => BONUS_CAMEL_123,
+            CharClass::Lower | CharClass::Upper if cur == CharClass::Number => BONUS_CAMEL_123,
+            _ => 0,
+        }
+    } else if cur == CharClass::White {
+        BONUS_BOUNDARY_WHITE
+    } else {
+        BONUS_NON_WORD
+    }
+}
+
+fn normalize(c: char, case_mode: CaseMode) -> char {
+    match case_mode {
+        CaseMode::Sensitive => c,
+        CaseMode::Insensitive => c.to_lowercase().next().unwrap_or(c),
+    }
+}
+
+/// Fuzzy match `pattern` against `text` in the style of fzf / skim.
+///
+/// `pattern` matches if all its characters appear in `text` in the same order.
+/// Returns `None` otherwise. The match is scored by its quality: consecutive
+/// matches and matches at the start of words score higher, gaps between matched
+/// characters lower the score. The scoring follows fzf's "v1" algorithm.
+pub fn fuzzy_match(pattern: &str, text: &str, case_mode: CaseMode) -> Option<FuzzyMatch> {
+    let pattern = pattern
+        .chars()
+        .map(|c| normalize(c, case_mode))
+        .collect::<Vec<_>>();
+    if pattern.is_empty() {
+        return Some(FuzzyMatch {
+            score: 0,
+            positions: Vec::new(),
+        });
+    }
+    let chars = text.char_indices().collect::<Vec<_>>();
+    let eq = |c: char, pat_idx: usize| normalize(c, case_mode) == pattern[pat_idx];
+
+    // Forward scan: find the end of the first occurrence of the pattern
+    let mut pat_idx = 0;
+    let mut end = None;
+    for (idx, &(_, c)) in chars.iter().enumerate() {
+        if eq(c, pat_idx) {
+            pat_idx += 1;
+            if pat_idx == pattern.len() {
+                end = Some(idx);
+                break;
+            }
+        }
+    }
+    let end = end?;
+
+    // Backward scan: find the shortest match that ends at `end`
+    let mut pat_idx = pattern.len() - 1;
+    let mut start = 0;
+    for idx in (0..=end).rev() {
+        if eq(chars[idx].1, pat_idx) {
+            if pat_idx == 0 {
+                start = idx;
+                break;
+            }
+            pat_idx -= 1;
+        }
+    }
+
+    // Score the match in `start..=end`
+    let mut score = 0;
+    let mut positions = Vec::with_capacity(pattern.len());
+    let mut pat_idx = 0;
+    let mut in_gap = false;
+    let mut consecutive = 0;
+    let mut first_bonus = 0;
+    let mut prev_class = match start {
+        0 => CharClass::White,
+        _ => CharClass::of(chars[start - 1].1),
+    };
+    for &(byte_idx, c) in &chars[start..=end] {
+        let class = CharClass::of(c);
+        if pat_idx < pattern.len() && eq(c, pat_idx) {
+            positions.push(byte_idx);
+            score += SCORE_MATCH;
+            let mut bonus = bonus_for(prev_class, class);
+            if consecutive == 0 {
+                first_bonus = bonus;
+            } else {
+                if bonus >= BONUS_BOUNDARY && bonus > first_bonus {
+                    first_bonus = bonus;
+                }
+                bonus = bonus.max(first_bonus).max(BONUS_CONSECUTIVE);
+            }
+            if pat_idx == 0 {
+                score += bonus * BONUS_FIRST_CHAR_MULTIPLIER;
+            } else {
+                score += bonus;
+            }
+            in_gap = false;
+            consecutive += 1;
+            pat_idx += 1;
+        } else {
+            score += if in_gap {
+                SCORE_GAP_EXTENSION
+            } else {
+                SCORE_GAP_START
+            };
+            in_gap = true;
+            consecutive = 0;
+            first_bonus = 0;
+        }
+        prev_class = class;
+    }
+    Some(FuzzyMatch { score, positions })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn score(pattern: &str, text: &str) -> Option<i64> {
+        fuzzy_match(pattern, text, CaseMode::Insensitive).map(|m| m.score)
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert!(fuzzy_match("gst", "git status", CaseMode::Sensitive).is_some());
+        assert!(fuzzy_match("gts", "git status", CaseMode::Sensitive).is_some());
+        assert!(fuzzy_match("sg", "git status", CaseMode::Sensitive).is_none());
+        assert!(fuzzy_match("GST", "git status", CaseMode::Sensitive).is_none());
+        assert!(fuzzy_match("GST", "git status", CaseMode::Insensitive).is_some());
+        assert!(fuzzy_match("gst", "", CaseMode::Insensitive).is_none());
+        assert_eq!(
+            fuzzy_match("", "git status", CaseMode::Insensitive),
+            Some(FuzzyMatch {
+                score: 0,
+                positions: vec![]
+            })
+        );
+
+        // The backward scan picks the shortest match
+        let m = fuzzy_match("ab", "a xx ab", CaseMode::Sensitive).unwrap();
+        assert_eq!(m.positions, vec![5, 6]);
+
+        // positions are byte offsets
+        let m = fuzzy_match("äb", "xä b", CaseMode::Sensitive).unwrap();
+        assert_eq!(m.positions, vec![1, 4]);
+        assert_eq!(m.ranges("xä b"), vec![1..3, 4..5]);
+
+        let m = fuzzy_match("doc", "docker", CaseMode::Sensitive).unwrap();
+        assert_eq!(m.ranges("docker"), vec![0..3]);
+    }
+
+    #[test]
+    fn test_fuzzy_scores() {
+        // consecutive beats scattered
+        assert!(score("stat", "git status") > score("stat", "s t a t"));
+        // word boundaries beat the middle of words
+        assert!(score("gs", "git status") > score("gs", "bigsur"));
+        // a match at the start beats a match in the middle of a word
+        assert!(score("kub", "kubectl get pods") > score("kub", "echo rekub"));
+        // smaller gaps beat larger ones
+        assert!(score("gp", "git push") > score("gp", "git stash pop"));
+    }
+}
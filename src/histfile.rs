@@ -1,10 +1,15 @@
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::fs::File;
-use std::io::BufRead as _;
+use std::io::BufRead;
 use std::io::BufReader;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
 
 use anyhow::Context;
 use chrono::DateTime;
+use chrono::Duration;
 use chrono::Local;
 use chrono::Utc;
 use itertools::Itertools;
@@ -13,11 +18,213 @@ use regex::Regex;
 use crate::MIN_REASONABLE_UNIXTIME;
 use crate::default_ts;
 
-pub fn open_and_parse_history_file(histfile: &str) -> anyhow::Result<Vec<HistEntry>> {
+mod fish;
+mod zsh;
+
+pub use fish::FishFormat;
+pub use fish::default_fish_history_file;
+pub use zsh::ZshFormat;
+
+/// A history file format that hgr can parse.
+///
+/// To add a new format, implement this trait, add a variant to [`FileFormat`]
+/// and, if the format can be detected automatically, add it to [`AUTO_FORMATS`].
+pub trait HistoryFormat {
+    /// The name of the format, used for logging
+    fn name(&self) -> &'static str;
+
+    /// Check if `head`, the first few kilobytes of a file, looks like this
+    /// format. Used by `--format=auto`
+    fn sniff(&self, head: &[u8]) -> bool;
+
+    /// Parse a history file in this format
+    fn parse(&self, read: &mut dyn BufRead) -> anyhow::Result<Vec<HistEntry>>;
+}
+
+/// The formats tried by `--format=auto`, in order. If none of them matches,
+/// the file is parsed as bash history (which handles plain files just fine).
+const AUTO_FORMATS: [&dyn HistoryFormat; 3] = [&FishFormat, &ZshFormat, &BashFormat];
+
+/// The history file formats that can be selected with `--format`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum FileFormat {
+    /// Detect the format from the contents of the file
+    Auto,
+    /// bash, with or without `#<unixtime>` timestamp lines
+    Bash,
+    /// zsh, with or without `EXTENDED_HISTORY`
+    Zsh,
+    /// fish's YAML-like `fish_history`
+    Fish,
+    /// One command per line, nothing else
+    Plain,
+}
+
+impl FileFormat {
+    /// Return the parser for this format, or `None` for `Auto`
+    fn parser(self) -> Option<&'static dyn HistoryFormat> {
+        match self {
+            FileFormat::Auto => None,
+            FileFormat::Bash => Some(&BashFormat),
+            FileFormat::Zsh => Some(&ZshFormat),
+            FileFormat::Fish => Some(&FishFormat),
+            FileFormat::Plain => Some(&PlainFormat),
+        }
+    }
+}
+
+/// Pick the parser for a file based on its first few kilobytes
+pub fn detect_format(head: &[u8]) -> &'static dyn HistoryFormat {
+    AUTO_FORMATS
+        .into_iter()
+        .find(|format| format.sniff(head))
+        .unwrap_or(&BashFormat)
+}
+
+/// Expand the `--histfile` arguments into a list of history files. Arguments
+/// containing glob meta characters (`*`, `?`, `[`) are expanded as globs. For
+/// a directory, all regular files directly in it are used.
+pub fn expand_histfile_args(args: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut ret = Vec::new();
+    for arg in args {
+        let paths = if arg.contains(['*', '?', '[']) {
+            glob::glob(arg)
+                .with_context(|| format!("Invalid glob pattern `{}`", arg))?
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("Expanding glob pattern `{}`", arg))?
+        } else {
+            vec![PathBuf::from(arg)]
+        };
+        if paths.is_empty() {
+            log::warn!("No history files match `{}`", arg);
+        }
+        for path in paths {
+            if path.is_dir() {
+                let mut files = std::fs::read_dir(&path)
+                    .with_context(|| format!("Reading directory `{}`", path.display()))?
+                    .map(|dirent| dirent.map(|d| d.path()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .with_context(|| format!("Reading directory `{}`", path.display()))?;
+                files.retain(|p| p.is_file());
+                files.sort();
+                ret.extend(files.iter().map(|p| p.to_string_lossy().into_owned()));
+            } else {
+                ret.push(path.to_string_lossy().into_owned());
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// Open and parse several history files and merge their entries into a single
+/// Vec, ordered by timestamp. The relative order of the entries from a single
+/// file is retained.
+pub fn open_and_parse_history_files(
+    histfiles: &[String],
+    format: FileFormat,
+) -> anyhow::Result<Vec<HistEntry>> {
+    let per_file = histfiles
+        .iter()
+        .map(|histfile| open_and_parse_history_file(histfile, format))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut entries = merge_entries(per_file);
+    for (seq, entry) in entries.iter_mut().enumerate() {
+        entry.seq = seq;
+    }
+    Ok(entries)
+}
+
+/// Merge several Vecs of entries, each in history file order, into a single Vec
+/// ordered by timestamp.
+fn merge_entries(per_file: Vec<Vec<HistEntry>>) -> Vec<HistEntry> {
+    per_file
+        .into_iter()
+        .kmerge_by(|a, b| a.ts < b.ts)
+        .collect_vec()
+}
+
+/// Open and parse a history file in the given format. With `FileFormat::Auto`
+/// the format is detected from the beginning of the file. The entries record
+/// `histfile` as their source.
+pub fn open_and_parse_history_file(
+    histfile: &str,
+    format: FileFormat,
+) -> anyhow::Result<Vec<HistEntry>> {
     log::debug!("Reading and parsing history file: {}", histfile);
     let read =
         File::open(histfile).with_context(|| format!("Opening history file: `{}`", histfile))?;
-    parse_history_file(read)
+    let mut reader = BufReader::new(read);
+    let parser = match format.parser() {
+        Some(parser) => parser,
+        None => {
+            let head = reader
+                .fill_buf()
+                .with_context(|| format!("Reading history file: `{}`", histfile))?;
+            detect_format(head)
+        }
+    };
+    log::debug!("Parsing `{}` as {} history", histfile, parser.name());
+    let mut entries = parser.parse(&mut reader)?;
+    let source: Rc<str> = Rc::from(histfile);
+    for entry in entries.iter_mut() {
+        entry.source = Some(source.clone());
+    }
+    Ok(entries)
+}
+
+/// bash history files. See [`parse_history_file`]
+pub struct BashFormat;
+
+impl HistoryFormat for BashFormat {
+    fn name(&self) -> &'static str {
+        "bash"
+    }
+
+    /// Any line with a bash timestamp
+    fn sniff(&self, head: &[u8]) -> bool {
+        head.split(|b| *b == b'\n').any(|line| {
+            matches!(
+                ParsedLine::parse(&String::from_utf8_lossy(line)),
+                ParsedLine::Timestamp(_)
+            )
+        })
+    }
+
+    fn parse(&self, read: &mut dyn BufRead) -> anyhow::Result<Vec<HistEntry>> {
+        parse_history_file(read)
+    }
+}
+
+/// Files with one command per line, without any timestamps
+pub struct PlainFormat;
+
+impl HistoryFormat for PlainFormat {
+    fn name(&self) -> &'static str {
+        "plain"
+    }
+
+    /// Plain files cannot be told apart from bash files without timestamps,
+    /// so they are never detected
+    fn sniff(&self, _head: &[u8]) -> bool {
+        false
+    }
+
+    fn parse(&self, read: &mut dyn BufRead) -> anyhow::Result<Vec<HistEntry>> {
+        parse_plain_history_file(read)
+    }
+}
+
+/// Parse a plain history file: every line is a separate command, all entries
+/// get `default_ts()` as timestamp
+pub fn parse_plain_history_file(read: impl std::io::Read) -> anyhow::Result<Vec<HistEntry>> {
+    let mut ret = Vec::new();
+    let reader = BufReader::new(read);
+    for (mut line_no, line) in reader.lines().enumerate() {
+        line_no += 1;
+        let line = line.with_context(|| format!("Error reading line number {}", line_no))?;
+        ret.push(HistEntry::new(default_ts(), line));
+    }
+    Ok(filter_invalid_entries(ret))
 }
 
 /// Parse a history file
@@ -45,10 +252,7 @@ pub fn parse_history_file(read: impl std::io::Read) -> anyhow::Result<Vec<HistEn
         state = match (&state, parsed) {
             (FileParseState::NoTimestamps, ParsedLine::Command(cmd)) => {
                 // No timestamp yet. Assume each line in the file is a single command
-                ret.push(HistEntry {
-                    ts: default_ts(),
-                    command: cmd,
-                });
+                ret.push(HistEntry::new(default_ts(), cmd));
                 FileParseState::NoTimestamps
             }
             (FileParseState::NoTimestamps, ParsedLine::Timestamp(ts)) => {
@@ -72,10 +276,7 @@ pub fn parse_history_file(read: impl std::io::Read) -> anyhow::Result<Vec<HistEn
                 FileParseState::LastWasTimestamp
             }
             (FileParseState::LastWasCommand, ParsedLine::Timestamp(ts)) => {
-                ret.push(HistEntry {
-                    ts: cur_ts,
-                    command: cur_lines.join("\n"),
-                });
+                ret.push(HistEntry::new(cur_ts, cur_lines.join("\n")));
                 cur_ts = ts;
                 cur_lines.clear();
                 FileParseState::LastWasTimestamp
@@ -84,13 +285,16 @@ pub fn parse_history_file(read: impl std::io::Read) -> anyhow::Result<Vec<HistEn
     }
     if state == FileParseState::LastWasCommand {
         // Need to flush the last command
-        ret.push(HistEntry {
-            ts: cur_ts,
-            command: cur_lines.join("\n"),
-        });
+        ret.push(HistEntry::new(cur_ts, cur_lines.join("\n")));
     }
 
-    Ok(ret
+    Ok(filter_invalid_entries(ret))
+}
+
+/// Remove entries that are empty or that contain control characters (other
+/// than newlines)
+fn filter_invalid_entries(entries: Vec<HistEntry>) -> Vec<HistEntry> {
+    entries
         .into_iter()
         .filter(|e| {
             let has_control_chars = e
@@ -98,38 +302,266 @@ pub fn parse_history_file(read: impl std::io::Read) -> anyhow::Result<Vec<HistEn
                 .chars()
                 .any(|c| c.is_ascii_control() && c != 0x0a as char);
             if has_control_chars || e.command.is_empty() {
-                log::info!("FOO FOO {:?} has controls chars or is empty", e);
+                log::info!("{:?} has controls chars or is empty", e);
                 return false;
             }
             true
         })
-        .collect_vec())
+        .collect_vec()
+}
+
+/// How duplicate commands are removed from the history
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum DedupMode {
+    /// Keep all entries
+    None,
+    /// Collapse runs of the same command into their first entry
+    Consecutive,
+    /// Keep only the first occurrence of each command
+    GlobalFirst,
+    /// Keep only the most recent occurrence of each command
+    GlobalLast,
+}
+
+impl DedupMode {
+    /// The next dedup mode, for toggling through them in interactive mode
+    pub fn next(self) -> Self {
+        match self {
+            DedupMode::None => DedupMode::Consecutive,
+            DedupMode::Consecutive => DedupMode::GlobalLast,
+            DedupMode::GlobalLast => DedupMode::GlobalFirst,
+            DedupMode::GlobalFirst => DedupMode::None,
+        }
+    }
+
+    /// The name shown in the interactive mode footer
+    pub fn name(self) -> &'static str {
+        match self {
+            DedupMode::None => "nodedup",
+            DedupMode::Consecutive => "consecutive",
+            DedupMode::GlobalFirst => "global-first",
+            DedupMode::GlobalLast => "global-last",
+        }
+    }
 }
 
-/// Deduplicate consecutive history entries that have the same command.
-/// The first instance of the command is retained.
-pub fn dedup_entries(entries: Vec<HistEntry>) -> Vec<HistEntry> {
-    let mut ret: Vec<HistEntry> = Vec::with_capacity(entries.len());
-    if entries.is_empty() {
-        return entries;
+/// Deduplicate history entries that have the same command according to `mode`.
+/// The retained entry absorbs the removed ones, i.e., its `count`, `first_seen`,
+/// and `last_seen` cover all of them.
+pub fn dedup_entries(entries: Vec<HistEntry>, mode: DedupMode) -> Vec<HistEntry> {
+    dedup_entries_by(entries, mode, |e| e, |e| e)
+}
+
+/// Like `dedup_entries`, but for items that hold a `HistEntry`, which `entry` and
+/// `entry_mut` return. `entry_mut` is only called for retained items that absorb
+/// duplicates, so, e.g., `Rc::make_mut` only clones those.
+pub fn dedup_entries_by<T>(
+    items: Vec<T>,
+    mode: DedupMode,
+    entry: impl Fn(&T) -> &HistEntry,
+    mut entry_mut: impl FnMut(&mut T) -> &mut HistEntry,
+) -> Vec<T> {
+    match mode {
+        DedupMode::None => items,
+        DedupMode::Consecutive => {
+            let mut ret: Vec<T> = Vec::with_capacity(items.len());
+            for item in items.into_iter() {
+                match ret.last_mut() {
+                    Some(prev) if entry(prev).command == entry(&item).command => {
+                        entry_mut(prev).absorb(entry(&item))
+                    }
+                    _ => ret.push(item),
+                }
+            }
+            ret
+        }
+        DedupMode::GlobalFirst => dedup_entries_global(items, entry, entry_mut),
+        DedupMode::GlobalLast => {
+            let mut ret = dedup_entries_global(items.into_iter().rev(), entry, entry_mut);
+            ret.reverse();
+            ret
+        }
     }
-    for e in entries.into_iter() {
-        if ret.last().is_some_and(|prev| prev.command == e.command) {
-            continue;
+}
+
+/// Keep the first item of each command in `items`
+fn dedup_entries_global<T>(
+    items: impl IntoIterator<Item = T>,
+    entry: impl Fn(&T) -> &HistEntry,
+    mut entry_mut: impl FnMut(&mut T) -> &mut HistEntry,
+) -> Vec<T> {
+    let mut ret: Vec<T> = Vec::new();
+    let mut index_by_command: HashMap<String, usize> = HashMap::new();
+    for item in items {
+        match index_by_command.get(&entry(&item).command) {
+            Some(&idx) => entry_mut(&mut ret[idx]).absorb(entry(&item)),
+            None => {
+                index_by_command.insert(entry(&item).command.clone(), ret.len());
+                ret.push(item);
+            }
         }
-        ret.push(e);
     }
     ret
 }
 
+/// The minimum number of hex digits used for abbreviated entry IDs
+const MIN_ID_LEN: usize = 7;
+
+/// The number of hex digits needed to print the IDs of `entries` unambiguously,
+/// but at least `MIN_ID_LEN`. Entries with the same timestamp and command have
+/// the same ID, they are not considered ambiguous.
+pub fn id_abbrev_len(entries: &[HistEntry]) -> usize {
+    let ids = entries.iter().map(|e| e.id()).sorted_unstable().dedup();
+    let common_prefix_len = ids
+        .tuple_windows()
+        .map(|(a, b)| ((a ^ b).leading_zeros() / 4) as usize)
+        .max()
+        .unwrap_or(0);
+    (common_prefix_len + 1).clamp(MIN_ID_LEN, 16)
+}
+
+/// Find the entry whose ID starts with `id_prefix` (lowercase hex digits). It's
+/// an error if there is no such entry, or if the prefix matches entries with
+/// different commands.
+pub fn find_entry_by_id<'a>(
+    entries: &'a [HistEntry],
+    id_prefix: &str,
+) -> anyhow::Result<&'a HistEntry> {
+    let candidates = entries
+        .iter()
+        .filter(|e| e.full_id().starts_with(id_prefix))
+        .unique_by(|e| &e.command)
+        .collect_vec();
+    match candidates.as_slice() {
+        [] => Err(anyhow::anyhow!(
+            "No entry with ID `{}` in history",
+            id_prefix
+        )),
+        [entry] => Ok(entry),
+        _ => Err(anyhow::anyhow!(
+            "ID `{}` is ambiguous. Candidates are:\n{}",
+            id_prefix,
+            candidates
+                .iter()
+                .map(|e| format!("{} {}", e.full_id(), e))
+                .join("\n")
+        )),
+    }
+}
+
+/// The length of the longest source name of `entries`. Used to align the
+/// source column when printing entries
+pub fn max_source_name_len(entries: &[HistEntry]) -> usize {
+    entries
+        .iter()
+        .filter_map(|e| e.source_name())
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0)
+}
+
 /// Represents a history entry
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct HistEntry {
     pub ts: DateTime<Utc>,
     pub command: String,
+    /// How long the command ran, if the history file records it (zsh)
+    pub duration: Option<Duration>,
+    /// Paths the command referenced, if the history file records them (fish)
+    pub paths: Vec<String>,
+    /// The history file this entry was read from
+    pub source: Option<Rc<str>>,
+    /// The position of this entry in the merged history, before duplicates
+    /// were removed
+    pub seq: usize,
+    /// How many times the command was run. This is larger than one if
+    /// duplicates of this entry were removed
+    pub count: usize,
+    /// The earliest timestamp of the removed duplicates, if they had an
+    /// earlier one than `ts`
+    first_seen: Option<DateTime<Utc>>,
+    /// The latest timestamp of the removed duplicates, if they had a later
+    /// one than `ts`
+    last_seen: Option<DateTime<Utc>>,
 }
 
 impl HistEntry {
+    pub fn new(ts: DateTime<Utc>, command: impl Into<String>) -> Self {
+        Self {
+            ts,
+            command: command.into(),
+            duration: None,
+            paths: Vec::new(),
+            source: None,
+            seq: 0,
+            count: 1,
+            first_seen: None,
+            last_seen: None,
+        }
+    }
+
+    /// The earliest timestamp of this entry and its removed duplicates
+    pub fn first_seen(&self) -> DateTime<Utc> {
+        self.first_seen.unwrap_or(self.ts)
+    }
+
+    /// The latest timestamp of this entry and its removed duplicates
+    pub fn last_seen(&self) -> DateTime<Utc> {
+        self.last_seen.unwrap_or(self.ts)
+    }
+
+    /// Merge the occurrence statistics of `dup`, a duplicate of this entry that
+    /// is being removed
+    fn absorb(&mut self, dup: &HistEntry) {
+        self.count += dup.count;
+        if dup.first_seen() < self.first_seen() {
+            self.first_seen = Some(dup.first_seen());
+        }
+        if dup.last_seen() > self.last_seen() {
+            self.last_seen = Some(dup.last_seen());
+        }
+    }
+
+    /// The file name (without directories) of the history file this entry was
+    /// read from
+    pub fn source_name(&self) -> Option<&str> {
+        let source = self.source.as_deref()?;
+        Some(
+            Path::new(source)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or(source),
+        )
+    }
+
+    /// A stable ID for this entry, derived from the timestamp and the command.
+    /// We use 64 bit FNV-1a, since the hash must not change between releases or
+    /// platforms.
+    pub fn id(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+        self.ts
+            .timestamp()
+            .to_le_bytes()
+            .iter()
+            .chain(self.command.as_bytes())
+            .fold(FNV_OFFSET_BASIS, |hash, b| {
+                (hash ^ u64::from(*b)).wrapping_mul(FNV_PRIME)
+            })
+    }
+
+    /// The ID as 16 hex digits
+    pub fn full_id(&self) -> String {
+        format!("{:016x}", self.id())
+    }
+
+    /// The ID abbreviated to `len` hex digits
+    pub fn short_id(&self, len: usize) -> String {
+        let mut id = self.full_id();
+        id.truncate(len);
+        id
+    }
+
     /// Check if this entry matches the search criteria.
     ///
     /// In order to be considered a match, this entry must match *all* regexes
@@ -140,12 +572,17 @@ impl HistEntry {
     }
 
     pub fn ts_as_string(&self) -> String {
-        let local_time = DateTime::<Local>::from(self.ts);
-        let formatted_time = local_time.format("%Y-%m-%d %H:%M:%S");
-        formatted_time.to_string()
+        format_ts(self.ts)
     }
 }
 
+/// Format `ts` in local time, the way timestamps of entries are shown
+pub fn format_ts(ts: DateTime<Utc>) -> String {
+    let local_time = DateTime::<Local>::from(ts);
+    let formatted_time = local_time.format("%Y-%m-%d %H:%M:%S");
+    formatted_time.to_string()
+}
+
 impl Display for HistEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}  {}", self.ts_as_string(), self.command)
@@ -192,17 +629,12 @@ impl ParsedLine {
 
 #[cfg(test)]
 mod test {
-    use chrono::Duration;
-
     use crate::default_ts;
 
     use super::*;
 
     pub fn newentry(ts: DateTime<Utc>, command: &str) -> HistEntry {
-        HistEntry {
-            ts,
-            command: command.to_owned(),
-        }
+        HistEntry::new(ts, command)
     }
 
     #[test]
@@ -260,10 +692,7 @@ mod test {
 
     #[test]
     fn test_parse_file_no_timestamp() {
-        let mkentry = |cmd: &str| HistEntry {
-            ts: default_ts(),
-            command: cmd.to_owned(),
-        };
+        let mkentry = |cmd: &str| HistEntry::new(default_ts(), cmd);
         let expected = vec![mkentry("foo"), mkentry("bar"), mkentry("foobar baz")];
         let hist = "foo\nbar\nfoobar baz\n".as_bytes();
         assert_eq!(parse_history_file(hist).unwrap(), expected);
@@ -275,13 +704,9 @@ mod test {
 
     #[test]
     fn test_parse_file_timestamps() {
-        let mkentry = |ts, cmd: &str| HistEntry {
-            ts: DateTime::from_timestamp(ts, 0).unwrap(),
-            command: cmd.to_owned(),
-        };
-        let mkmultiline = |ts, cmds: &[&str]| HistEntry {
-            ts: DateTime::from_timestamp(ts, 0).unwrap(),
-            command: cmds.join("\n"),
+        let mkentry = |ts, cmd: &str| HistEntry::new(DateTime::from_timestamp(ts, 0).unwrap(), cmd);
+        let mkmultiline = |ts, cmds: &[&str]| {
+            HistEntry::new(DateTime::from_timestamp(ts, 0).unwrap(), cmds.join("\n"))
         };
         // First commands have not timestamps, then we use timestamps
         let hist = "foo\n\
@@ -341,13 +766,9 @@ mod test {
 
     #[test]
     fn test_parse_file_timestamps_and_whitespace() {
-        let mkentry = |ts, cmd: &str| HistEntry {
-            ts: DateTime::from_timestamp(ts, 0).unwrap(),
-            command: cmd.to_owned(),
-        };
-        let mkmultiline = |ts, cmds: &[&str]| HistEntry {
-            ts: DateTime::from_timestamp(ts, 0).unwrap(),
-            command: cmds.join("\n"),
+        let mkentry = |ts, cmd: &str| HistEntry::new(DateTime::from_timestamp(ts, 0).unwrap(), cmd);
+        let mkmultiline = |ts, cmds: &[&str]| {
+            HistEntry::new(DateTime::from_timestamp(ts, 0).unwrap(), cmds.join("\n"))
         };
         let hist = "#1262305001\nfoobar \n#1262305005\n\nbar bar bar\n\n".as_bytes();
         let res = parse_history_file(hist).unwrap();
@@ -360,13 +781,69 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_parse_plain_history_file() {
+        let hist = "foo\n#1262305001\nbar baz\n\n".as_bytes();
+        assert_eq!(
+            parse_plain_history_file(hist).unwrap(),
+            vec![
+                HistEntry::new(default_ts(), "foo"),
+                HistEntry::new(default_ts(), "#1262305001"),
+                HistEntry::new(default_ts(), "bar baz"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let detect = |head: &str| detect_format(head.as_bytes()).name();
+        assert_eq!(detect("- cmd: ls\n  when: 1700000000\n"), "fish");
+        assert_eq!(detect(": 1700000000:0;ls\n"), "zsh");
+        assert_eq!(detect("ls\n#1700000000\nfoo\n"), "bash");
+        // Nothing matches ==> bash
+        assert_eq!(detect("ls\nfoo\n"), "bash");
+        assert_eq!(detect(""), "bash");
+    }
+
+    #[test]
+    fn test_merge_entries() {
+        let t0 = default_ts();
+        let mk_ts = |mins| t0 + Duration::minutes(mins);
+        let file1 = vec![
+            newentry(t0, "no timestamp"),
+            newentry(mk_ts(5), "a"),
+            newentry(mk_ts(20), "b"),
+            // Out of order entries from the same file stay where they are
+            newentry(mk_ts(10), "c"),
+        ];
+        let file2 = vec![newentry(mk_ts(7), "x"), newentry(mk_ts(30), "y")];
+        assert_eq!(
+            merge_entries(vec![file1, file2]),
+            vec![
+                newentry(t0, "no timestamp"),
+                newentry(mk_ts(5), "a"),
+                newentry(mk_ts(7), "x"),
+                newentry(mk_ts(20), "b"),
+                newentry(mk_ts(10), "c"),
+                newentry(mk_ts(30), "y"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_source_name() {
+        let mut entry = newentry(default_ts(), "ls");
+        assert_eq!(entry.source_name(), None);
+        entry.source = Some(Rc::from("/home/foo/.bash_history.d/session1"));
+        assert_eq!(entry.source_name(), Some("session1"));
+        entry.source = Some(Rc::from("histfile"));
+        assert_eq!(entry.source_name(), Some("histfile"));
+    }
+
     #[test]
     fn test_matches() {
         let mk_re = |p: &str| Regex::new(p).unwrap();
-        let entry = HistEntry {
-            ts: default_ts(),
-            command: "I am the command\nwith many lines. Foobar".to_owned(),
-        };
+        let entry = HistEntry::new(default_ts(), "I am the command\nwith many lines. Foobar");
         assert!(entry.matches(&[mk_re("am the"), mk_re("many")], &[]));
         assert!(entry.matches(&[], &[]));
         assert!(!entry.matches(&[], &[mk_re("many")]));
@@ -379,6 +856,70 @@ mod test {
         assert!(entry.matches(&[mk_re("am the"), mk_re("am the")], &[]));
     }
 
+    #[test]
+    fn test_entry_id() {
+        let t0 = default_ts();
+        let entry = newentry(t0, "ls -la");
+        // The ID must be stable
+        assert_eq!(entry.full_id(), "e3e457df0dfb4955");
+        assert_eq!(entry.short_id(7), "e3e457d");
+        assert_eq!(entry.id(), newentry(t0, "ls -la").id());
+        assert_ne!(entry.id(), newentry(t0, "ls -l").id());
+        assert_ne!(
+            entry.id(),
+            newentry(t0 + Duration::seconds(1), "ls -la").id()
+        );
+    }
+
+    #[test]
+    fn test_id_abbrev_len() {
+        let t0 = default_ts();
+        assert_eq!(id_abbrev_len(&[]), MIN_ID_LEN);
+        let entries = (0..100)
+            .map(|i| newentry(t0 + Duration::seconds(i), "ls"))
+            .collect_vec();
+        let len = id_abbrev_len(&entries);
+        assert!(len >= MIN_ID_LEN);
+        assert_eq!(
+            entries.iter().map(|e| e.short_id(len)).unique().count(),
+            100
+        );
+        // Duplicate entries are not ambiguous
+        let entries = vec![newentry(t0, "ls"), newentry(t0, "ls")];
+        assert_eq!(id_abbrev_len(&entries), MIN_ID_LEN);
+    }
+
+    #[test]
+    fn test_find_entry_by_id() {
+        let t0 = default_ts();
+        let entries = (0..100)
+            .map(|i| newentry(t0 + Duration::seconds(i), &format!("cmd {}", i)))
+            .collect_vec();
+        let entry = &entries[42];
+        assert_eq!(find_entry_by_id(&entries, &entry.full_id()).unwrap(), entry);
+        assert_eq!(
+            find_entry_by_id(&entries, &entry.short_id(8)).unwrap(),
+            entry
+        );
+        // With 100 entries, some share the first hex digit
+        assert!(find_entry_by_id(&entries, &entry.short_id(1)).is_err());
+        assert!(find_entry_by_id(&entries, "").is_err());
+        // No such ID
+        let unused = (0..16)
+            .map(|d| format!("{:x}", d))
+            .flat_map(|a| (0..16).map(move |d| format!("{}{:x}", a, d)))
+            .find(|prefix| !entries.iter().any(|e| e.full_id().starts_with(prefix)))
+            .unwrap();
+        assert!(find_entry_by_id(&entries, &unused).is_err());
+
+        // Duplicate entries are not ambiguous
+        let dup = vec![newentry(t0, "ls"), newentry(t0, "ls")];
+        assert_eq!(
+            find_entry_by_id(&dup, &dup[0].short_id(4)).unwrap(),
+            &dup[0]
+        );
+    }
+
     #[test]
     fn test_dedup_entries() {
         let t0 = default_ts();
@@ -393,13 +934,35 @@ mod test {
             newentry(t3, "rm foobar"),
             newentry(t4, "ls -la"),
         ];
+        let with_stats = |ts, cmd, count, first_seen, last_seen| HistEntry {
+            count,
+            first_seen: (first_seen != ts).then_some(first_seen),
+            last_seen: (last_seen != ts).then_some(last_seen),
+            ..newentry(ts, cmd)
+        };
+
+        assert_eq!(dedup_entries(orig.clone(), DedupMode::None), orig);
         assert_eq!(
-            dedup_entries(orig),
+            dedup_entries(orig.clone(), DedupMode::Consecutive),
             vec![
                 newentry(t0, "ls -la"),
-                newentry(t1, "rm foobar"),
+                with_stats(t1, "rm foobar", 3, t1, t3),
                 newentry(t4, "ls -la"),
             ]
         );
+        assert_eq!(
+            dedup_entries(orig.clone(), DedupMode::GlobalFirst),
+            vec![
+                with_stats(t0, "ls -la", 2, t0, t4),
+                with_stats(t1, "rm foobar", 3, t1, t3),
+            ]
+        );
+        assert_eq!(
+            dedup_entries(orig, DedupMode::GlobalLast),
+            vec![
+                with_stats(t3, "rm foobar", 3, t1, t3),
+                with_stats(t4, "ls -la", 2, t0, t4),
+            ]
+        );
     }
 }
@@ -0,0 +1,193 @@
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use chrono::DateTime;
+
+use super::HistEntry;
+use super::HistoryFormat;
+use super::filter_invalid_entries;
+use crate::default_ts;
+
+/// Return the location of fish's default history file, if it exists.
+/// This is `$XDG_DATA_HOME/fish/fish_history`, with `XDG_DATA_HOME`
+/// defaulting to `~/.local/share`
+pub fn default_fish_history_file() -> Option<PathBuf> {
+    let data_home = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/share"),
+    };
+    let path = data_home.join("fish/fish_history");
+    path.is_file().then_some(path)
+}
+
+/// fish history files. See [`parse_fish_history_file`]
+pub struct FishFormat;
+
+impl HistoryFormat for FishFormat {
+    fn name(&self) -> &'static str {
+        "fish"
+    }
+
+    fn sniff(&self, head: &[u8]) -> bool {
+        looks_like_fish_history(head)
+    }
+
+    fn parse(&self, read: &mut dyn BufRead) -> anyhow::Result<Vec<HistEntry>> {
+        parse_fish_history_file(read)
+    }
+}
+
+/// Check if the beginning of a file looks like a fish history file. I.e., the
+/// first non-empty line starts with `- cmd: `
+fn looks_like_fish_history(head: &[u8]) -> bool {
+    head.split(|b| *b == b'\n')
+        .find(|line| !line.is_empty())
+        .is_some_and(|line| line.starts_with(b"- cmd: "))
+}
+
+/// Parse a fish history file
+///
+/// fish uses a YAML-like format. Each entry starts with a `- cmd: <command>`
+/// line, followed by indented keys. We use `when: <unixtime>` for the timestamp
+/// and keep the list items following `paths:` as metadata. Other keys are
+/// ignored. Commands and paths are unescaped (`\\` and `\n`).
+fn parse_fish_history_file(read: impl std::io::Read) -> anyhow::Result<Vec<HistEntry>> {
+    let mut ret = Vec::new();
+    let mut cur_entry: Option<HistEntry> = None;
+    let mut in_paths = false;
+
+    let reader = BufReader::new(read);
+    for (mut line_no, line) in reader.lines().enumerate() {
+        line_no += 1;
+        let line = line.with_context(|| format!("Error reading line number {}", line_no))?;
+        log::trace!("Read line {}: `{}`", line_no, line);
+        if let Some(cmd) = line.strip_prefix("- cmd: ") {
+            ret.extend(cur_entry.take());
+            cur_entry = Some(HistEntry::new(default_ts(), unescape(cmd)));
+            in_paths = false;
+            continue;
+        }
+        let Some(entry) = cur_entry.as_mut() else {
+            log::info!("Ignoring line {} outside of an entry: `{}`", line_no, line);
+            continue;
+        };
+        if let Some(when) = line.strip_prefix("  when: ") {
+            in_paths = false;
+            match when
+                .parse()
+                .ok()
+                .and_then(|t| DateTime::from_timestamp(t, 0))
+            {
+                Some(ts) => entry.ts = ts,
+                None => log::info!("Invalid timestamp at line {}: `{}`", line_no, line),
+            }
+        } else if line == "  paths:" {
+            in_paths = true;
+        } else if let Some(path) = line.strip_prefix("    - ")
+            && in_paths
+        {
+            entry.paths.push(unescape(path));
+        } else {
+            in_paths = false;
+        }
+    }
+    ret.extend(cur_entry);
+
+    Ok(filter_invalid_entries(ret))
+}
+
+/// Undo fish's escaping of history entries: `\\` is a backslash and `\n` is a
+/// newline. Any other backslash is retained as-is.
+fn unescape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.peek() {
+                Some('\\') => {
+                    chars.next();
+                    ret.push('\\');
+                }
+                Some('n') => {
+                    chars.next();
+                    ret.push('\n');
+                }
+                _ => ret.push(c),
+            }
+        } else {
+            ret.push(c);
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mkentry(ts: i64, cmd: &str, paths: &[&str]) -> HistEntry {
+        let mut entry = HistEntry::new(DateTime::from_timestamp(ts, 0).unwrap(), cmd);
+        entry.paths = paths.iter().map(|p| p.to_string()).collect();
+        entry
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("foo bar"), "foo bar");
+        assert_eq!(unescape(r"echo a\nb"), "echo a\nb");
+        assert_eq!(unescape(r"echo a\\nb"), r"echo a\nb");
+        assert_eq!(unescape(r"echo \$HOME \\"), r"echo \$HOME \");
+        assert_eq!(unescape(r"trailing\"), r"trailing\");
+    }
+
+    #[test]
+    fn test_looks_like_fish_history() {
+        assert!(looks_like_fish_history(b"- cmd: ls\n  when: 1700000000\n"));
+        assert!(looks_like_fish_history(b"\n- cmd: ls\n"));
+        assert!(!looks_like_fish_history(b"ls\n- cmd: ls\n"));
+        assert!(!looks_like_fish_history(b": 1700000000:12;ls\n"));
+        assert!(!looks_like_fish_history(b""));
+    }
+
+    #[test]
+    fn test_parse_fish_history_file() {
+        let hist = "- cmd: git status\n  \
+            when: 1700000000\n\
+            - cmd: vim src/main.rs src/lib.rs\n  \
+            when: 1700000005\n  \
+            paths:\n    \
+            - src/main.rs\n    \
+            - src/lib.rs\n\
+            - cmd: for i in 1 2\\n    echo $i\\nend\n  \
+            when: 1700000010\n"
+            .as_bytes();
+        assert_eq!(
+            parse_fish_history_file(hist).unwrap(),
+            vec![
+                mkentry(1700000000, "git status", &[]),
+                mkentry(
+                    1700000005,
+                    "vim src/main.rs src/lib.rs",
+                    &["src/main.rs", "src/lib.rs"]
+                ),
+                mkentry(1700000010, "for i in 1 2\n    echo $i\nend", &[]),
+            ]
+        );
+
+        // Missing or invalid timestamp, unknown keys
+        let hist = "- cmd: ls\n  \
+            foo: bar\n\
+            - cmd: pwd\n  \
+            when: abc"
+            .as_bytes();
+        assert_eq!(
+            parse_fish_history_file(hist).unwrap(),
+            vec![
+                HistEntry::new(default_ts(), "ls"),
+                HistEntry::new(default_ts(), "pwd"),
+            ]
+        );
+    }
+}
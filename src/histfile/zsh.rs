@@ -0,0 +1,220 @@
+use std::io::BufRead;
+use std::io::BufReader;
+
+use anyhow::Context;
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+
+use super::HistEntry;
+use super::HistoryFormat;
+use super::filter_invalid_entries;
+use crate::default_ts;
+
+/// zsh "metafies" bytes it considers special when writing the history file:
+/// such a byte is written as `META` followed by the byte XORed with 0x20.
+const META: u8 = 0x83;
+
+/// zsh history files. See [`parse_zsh_history_file`]
+pub struct ZshFormat;
+
+impl HistoryFormat for ZshFormat {
+    fn name(&self) -> &'static str {
+        "zsh"
+    }
+
+    fn sniff(&self, head: &[u8]) -> bool {
+        looks_like_zsh_history(head)
+    }
+
+    fn parse(&self, read: &mut dyn BufRead) -> anyhow::Result<Vec<HistEntry>> {
+        parse_zsh_history_file(read)
+    }
+}
+
+/// Check if the beginning of a file looks like a zsh `EXTENDED_HISTORY` file.
+/// I.e., the first non-empty line has the form `: <start>:<elapsed>;<command>`
+fn looks_like_zsh_history(head: &[u8]) -> bool {
+    head.split(|b| *b == b'\n')
+        .find(|line| !line.is_empty())
+        .is_some_and(|line| parse_extended_prefix(&String::from_utf8_lossy(line)).is_some())
+}
+
+/// Parse a zsh history file
+///
+/// Each entry is either a plain command line or, with `setopt EXTENDED_HISTORY`,
+/// a line of the form `: <start>:<elapsed>;<command>`. Commands spanning several
+/// lines are written with a trailing backslash on every line but the last. Entries
+/// without the extended prefix get `default_ts()` as timestamp.
+fn parse_zsh_history_file(read: impl std::io::Read) -> anyhow::Result<Vec<HistEntry>> {
+    let mut ret = Vec::new();
+    // The entry we are currently reading if its last line ended in a backslash
+    let mut cur_entry: Option<HistEntry> = None;
+
+    let mut reader = BufReader::new(read);
+    let mut buf = Vec::new();
+    let mut line_no = 0;
+    loop {
+        buf.clear();
+        line_no += 1;
+        let num_read = reader
+            .read_until(b'\n', &mut buf)
+            .with_context(|| format!("Error reading line number {}", line_no))?;
+        if num_read == 0 {
+            break;
+        }
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+        }
+        let line = String::from_utf8_lossy(&unmetafy(&buf)).into_owned();
+        log::trace!("Read line {}: `{}`", line_no, line);
+
+        let mut entry = match cur_entry.take() {
+            Some(mut entry) => {
+                entry.command.push('\n');
+                entry.command.push_str(&line);
+                entry
+            }
+            None => match parse_extended_prefix(&line) {
+                Some((ts, duration, cmd)) => {
+                    let mut entry = HistEntry::new(ts, cmd);
+                    entry.duration = Some(duration);
+                    entry
+                }
+                None => HistEntry::new(default_ts(), line),
+            },
+        };
+        if entry.command.ends_with('\\') {
+            // Line continuation. Drop the backslash and keep reading
+            entry.command.pop();
+            cur_entry = Some(entry);
+        } else {
+            ret.push(entry);
+        }
+    }
+    if let Some(entry) = cur_entry {
+        // The file ended with a continuation line. Flush what we have.
+        ret.push(entry);
+    }
+
+    Ok(filter_invalid_entries(ret))
+}
+
+/// Parse the `: <start>:<elapsed>;` prefix of an extended history line. Returns
+/// the start time, the elapsed time and the remainder of the line (the command)
+fn parse_extended_prefix(line: &str) -> Option<(DateTime<Utc>, Duration, &str)> {
+    let rest = line.strip_prefix(": ")?;
+    let (times, cmd) = rest.split_once(';')?;
+    let (start, elapsed) = times.split_once(':')?;
+    let ts = DateTime::from_timestamp(start.trim_start().parse().ok()?, 0)?;
+    let duration = Duration::try_seconds(elapsed.parse().ok()?)?;
+    Some((ts, duration, cmd))
+}
+
+/// Undo zsh's metafication of special bytes
+fn unmetafy(buf: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(buf.len());
+    let mut iter = buf.iter();
+    while let Some(b) = iter.next() {
+        if *b == META {
+            if let Some(next) = iter.next() {
+                ret.push(next ^ 0x20);
+            }
+        } else {
+            ret.push(*b);
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mkentry(ts: i64, secs: i64, cmd: &str) -> HistEntry {
+        let mut entry = HistEntry::new(DateTime::from_timestamp(ts, 0).unwrap(), cmd);
+        entry.duration = Some(Duration::seconds(secs));
+        entry
+    }
+
+    #[test]
+    fn test_parse_extended_prefix() {
+        assert_eq!(
+            parse_extended_prefix(": 1700000000:12;git status"),
+            Some((
+                DateTime::from_timestamp(1700000000, 0).unwrap(),
+                Duration::seconds(12),
+                "git status"
+            ))
+        );
+        // zsh pads the start time in some versions
+        assert_eq!(
+            parse_extended_prefix(":  1700000000:0;ls;ls"),
+            Some((
+                DateTime::from_timestamp(1700000000, 0).unwrap(),
+                Duration::zero(),
+                "ls;ls"
+            ))
+        );
+        assert_eq!(parse_extended_prefix("git status"), None);
+        assert_eq!(parse_extended_prefix(": foo:12;git status"), None);
+        assert_eq!(parse_extended_prefix(": 1700000000;git status"), None);
+        assert_eq!(parse_extended_prefix(": 1700000000:12 git status"), None);
+    }
+
+    #[test]
+    fn test_looks_like_zsh_history() {
+        assert!(looks_like_zsh_history(b": 1700000000:12;git status\n"));
+        assert!(looks_like_zsh_history(b"\n: 1700000000:12;git status\nfoo"));
+        assert!(!looks_like_zsh_history(b"#1700000000\ngit status\n"));
+        assert!(!looks_like_zsh_history(b"git status\n: 1700000000:12;ls\n"));
+        assert!(!looks_like_zsh_history(b""));
+    }
+
+    #[test]
+    fn test_parse_zsh_history_file() {
+        let hist = ": 1700000000:12;git status\n\
+            : 1700000005:0;for i in 1 2; do\\\n\
+            echo $i\\\n\
+            done\n\
+            : 1700000010:3;ls -la\n"
+            .as_bytes();
+        assert_eq!(
+            parse_zsh_history_file(hist).unwrap(),
+            vec![
+                mkentry(1700000000, 12, "git status"),
+                mkentry(1700000005, 0, "for i in 1 2; do\necho $i\ndone"),
+                mkentry(1700000010, 3, "ls -la"),
+            ]
+        );
+
+        // Without EXTENDED_HISTORY and no trailing newline
+        let hist = "git status\necho foo\\\nbar".as_bytes();
+        assert_eq!(
+            parse_zsh_history_file(hist).unwrap(),
+            vec![
+                HistEntry::new(default_ts(), "git status"),
+                HistEntry::new(default_ts(), "echo foo\nbar"),
+            ]
+        );
+
+        // File ends in the middle of a multi-line command
+        let hist = ": 1700000000:1;echo foo\\\n".as_bytes();
+        assert_eq!(
+            parse_zsh_history_file(hist).unwrap(),
+            vec![mkentry(1700000000, 1, "echo foo")]
+        );
+    }
+
+    #[test]
+    fn test_unmetafy() {
+        // `ą` is 0xc4 0x85 in UTF-8. zsh writes 0x85 as META, 0xa5
+        let hist = b": 1700000000:0;echo \xc4\x83\xa5\n";
+        assert_eq!(
+            parse_zsh_history_file(&hist[..]).unwrap(),
+            vec![mkentry(1700000000, 0, "echo ą")]
+        );
+        assert_eq!(unmetafy(b"abc"), b"abc");
+        assert_eq!(unmetafy(b"a\x83"), b"a");
+    }
+}
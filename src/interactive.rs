@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+use std::ops::Range;
 use std::rc::Rc;
 
 use itertools::Itertools;
@@ -15,6 +17,7 @@ use ratatui::style::Modifier;
 use ratatui::style::Style;
 use ratatui::symbols;
 use ratatui::text::Line;
+use ratatui::text::Span;
 use ratatui::widgets::Block;
 use ratatui::widgets::Borders;
 use ratatui::widgets::HighlightSpacing;
@@ -22,27 +25,99 @@ use ratatui::widgets::List;
 use ratatui::widgets::ListItem;
 use ratatui::widgets::ListState;
 use ratatui::widgets::Paragraph;
-use regex::Regex;
+use ratatui::widgets::Wrap;
 use tui_input::Input;
 use tui_input::backend::crossterm::EventHandler;
 
 use crate::CaseMode;
+use crate::MatchMode;
+use crate::histfile::DedupMode;
 use crate::histfile::HistEntry;
-use crate::raw_pattern_to_regex;
+use crate::histfile::dedup_entries_by;
+use crate::histfile::format_ts;
+use crate::histfile::max_source_name_len;
+use crate::query::Query;
+use crate::rank::Ranker;
+use crate::rank::SortOrder;
 
 const HEADER_FOOTER_STYLE: Style = Style::new().fg(Color::White).bg(Color::Blue);
+const ERROR_STYLE: Style = Style::new().fg(Color::White).bg(Color::Red);
+const MATCH_STYLE: Style = Style::new().fg(Color::Red).add_modifier(Modifier::BOLD);
 const SELECTED_STYLE: Style = Style::new()
     .add_modifier(Modifier::REVERSED)
     .add_modifier(Modifier::BOLD);
+const MARKED_STYLE: Style = Style::new().fg(Color::Yellow);
+const PREVIEW_LABEL_STYLE: Style = Style::new().add_modifier(Modifier::BOLD);
+const PREVIEW_CONTEXT_STYLE: Style = Style::new().add_modifier(Modifier::DIM);
 
-/// Run the interactive history selector
-pub fn run_interactive(
+/// How many commands before and after the selected entry the preview shows
+const PREVIEW_CONTEXT: usize = 2;
+
+/// Where the preview of the selected entry is shown
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum PreviewMode {
+    Hidden,
+    Bottom,
+    Side,
+}
+
+impl PreviewMode {
+    /// The next preview mode, for toggling through them
+    fn next(self) -> Self {
+        match self {
+            PreviewMode::Hidden => PreviewMode::Bottom,
+            PreviewMode::Bottom => PreviewMode::Side,
+            PreviewMode::Side => PreviewMode::Hidden,
+        }
+    }
+}
+
+/// The search settings of interactive mode. They can all be changed at runtime
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Settings {
+    pub case_mode: CaseMode,
+    pub match_mode: MatchMode,
+    pub sort_order: SortOrder,
+    pub dedup_mode: DedupMode,
+}
+
+/// How multiple selected entries are combined into a single command
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum MultiJoin {
+    /// Join with ` && `, so the commands run one after the other until one fails
+    And,
+    /// One command per line
+    Newline,
+    /// A bash script that stops at the first failing command
+    Script,
+}
+
+impl MultiJoin {
+    /// Combine the commands of `entries`
+    pub fn join(self, entries: &[HistEntry]) -> String {
+        let mut commands = entries.iter().map(|e| e.command.as_str());
+        match self {
+            MultiJoin::And => commands.join(" && "),
+            MultiJoin::Newline => commands.join("\n"),
+            MultiJoin::Script => {
+                format!("#!/usr/bin/env bash\nset -e\n\n{}\n", commands.join("\n"))
+            }
+        }
+    }
+}
+
+/// Run the interactive history selector. `entries` are the entries before
+/// deduplication, only deduplicated entries for which `prefilter` returns true
+/// are shown. Returns the selected entries in history order, empty if the
+/// selection was aborted.
+pub fn run_interactive<'a>(
     entries: Vec<HistEntry>,
     inital_search: String,
-    exclude_re: Vec<Regex>,
-    case_mode: CaseMode,
-) -> anyhow::Result<Option<HistEntry>> {
-    App::new(entries, inital_search, exclude_re, case_mode).run()
+    prefilter: impl Fn(&HistEntry) -> bool + 'a,
+    settings: Settings,
+    show_source: bool,
+) -> anyhow::Result<Vec<HistEntry>> {
+    App::new(entries, inital_search, prefilter, settings, show_source).run()
 }
 
 /// Representation of the filtered list of HistoryEntry together
@@ -50,17 +125,21 @@ pub fn run_interactive(
 #[derive(Default)]
 struct FilteredList<'a> {
     entries: Vec<Rc<HistEntryWrapper<'a>>>,
+    /// For each of `entries`, the byte ranges of the command that matched the
+    /// search terms
+    highlights: Vec<Vec<Range<usize>>>,
     state: ListState,
     pagination_num_lines: u16,
 }
 
 impl<'a> FilteredList<'a> {
     /// Create a new instance and select the last entry in the list
-    fn new(entries: Vec<Rc<HistEntryWrapper<'a>>>) -> Self {
+    fn new(entries: Vec<Rc<HistEntryWrapper<'a>>>, highlights: Vec<Vec<Range<usize>>>) -> Self {
         let mut state = ListState::default();
         state.select_last();
         Self {
             entries,
+            highlights,
             state,
             pagination_num_lines: 1,
         }
@@ -68,24 +147,59 @@ impl<'a> FilteredList<'a> {
 
     /// Return the selected HistoryEntry
     fn get_selected(&self) -> Option<HistEntry> {
-        match self.state.selected() {
-            Some(idx) if idx < self.entries.len() => Some(self.entries[idx].orig.clone()),
-            _ => None,
-        }
+        self.selected_entry().cloned()
     }
 
-    fn render(&mut self, area: Rect, frame: &mut Frame) {
+    /// Return a reference to the selected HistoryEntry. `select_last` selects
+    /// `usize::MAX` until the list is rendered, so the index is clamped
+    fn selected_entry(&self) -> Option<&HistEntry> {
+        let idx = self.state.selected()?;
+        let entry = self
+            .entries
+            .get(idx.min(self.entries.len().saturating_sub(1)))?;
+        Some(&entry.orig)
+    }
+
+    /// Render the list. Entries whose `seq` is in `marked` are shown with
+    /// `MARKED_STYLE`
+    fn render(&mut self, title: &str, marked: &HashSet<usize>, area: Rect, frame: &mut Frame) {
         let block = Block::new()
-            .title(Line::raw("Interactive History Search").centered())
+            .title(Line::raw(title).centered())
             .borders(Borders::TOP)
             .border_set(symbols::border::EMPTY)
             .border_style(HEADER_FOOTER_STYLE);
 
         self.pagination_num_lines = area.height / 2;
+        // Building highlighted lines is expensive, so we only do it for entries that
+        // can end up on screen. The list scrolls so that the selected entry is
+        // visible, which means the visible entries are within `height` entries of
+        // the offset or the selection.
+        let height = area.height as usize;
+        let selected = self
+            .state
+            .selected()
+            .unwrap_or(0)
+            .min(self.entries.len().saturating_sub(1));
+        let offset = self.state.offset();
+        let visible = offset.min(selected).saturating_sub(height)
+            ..offset.max(selected).saturating_add(height + 1);
         let items = self
             .entries
             .iter()
-            .map(|entry| ListItem::from(entry.lines.clone()))
+            .zip(&self.highlights)
+            .enumerate()
+            .map(|(idx, (entry, highlights))| {
+                let item = if visible.contains(&idx) && !highlights.is_empty() {
+                    ListItem::from(entry.highlighted_lines(highlights))
+                } else {
+                    ListItem::from(entry.lines.clone())
+                };
+                if !marked.is_empty() && marked.contains(&entry.orig.seq) {
+                    item.style(MARKED_STYLE)
+                } else {
+                    item
+                }
+            })
             .collect_vec();
         let list = List::new(items)
             .block(block)
@@ -114,10 +228,38 @@ impl<'a> FilteredList<'a> {
 }
 
 struct App<'a> {
+    /// All entries, before deduplication and `prefilter`. Deduplication and the
+    /// timeline share the wrapped entries instead of cloning them
+    all_entries: Vec<Rc<HistEntryWrapper<'a>>>,
+    prefilter: Box<dyn Fn(&HistEntry) -> bool + 'a>,
+    /// The deduplicated and prefiltered entries that the search input filters
     entries: Vec<Rc<HistEntryWrapper<'a>>>,
     filtered_entries: FilteredList<'a>,
-    case_mode: CaseMode,
+    settings: Settings,
+    ranker: Ranker,
     search_input: Input,
+    /// The last valid query, the results are its matches
+    query: Query,
+    /// The search input `query` was parsed from
+    query_input: String,
+    /// Why the current search input could not be parsed, shown in the footer
+    query_error: Option<String>,
+    preview_mode: PreviewMode,
+    /// The timeline of all entries around an entry, while it is shown. The search
+    /// and its results are kept for when the timeline is closed
+    timeline: Option<FilteredList<'a>>,
+    /// The entries marked with Tab, in the order they were marked. Marks are
+    /// identified by `seq`, since entries with the same command and without a
+    /// timestamp have the same ID
+    marked: Vec<HistEntry>,
+    /// The command being edited before it is accepted, if any
+    edit: Option<Edit>,
+}
+
+/// An entry whose command is being edited
+struct Edit {
+    entry: HistEntry,
+    input: Input,
 }
 
 /// The outcome of handling a key event.
@@ -125,32 +267,45 @@ enum HandleKeyRes {
     /// Continue processing
     Continue,
     /// We are done. Exit interactive mode. The contained
-    /// Option holds the selected entry (if any)
-    Return(Option<HistEntry>),
+    /// Vec holds the selected entries (if any)
+    Return(Vec<HistEntry>),
 }
 
-impl App<'_> {
+impl<'a> App<'a> {
     fn new(
         entries: Vec<HistEntry>,
         inital_search: String,
-        exclude_re: Vec<Regex>,
-        case_mode: CaseMode,
+        prefilter: impl Fn(&HistEntry) -> bool + 'a,
+        settings: Settings,
+        show_source: bool,
     ) -> Self {
+        let source_width = show_source.then(|| max_source_name_len(&entries));
+        let ranker = Ranker::new(&entries, chrono::Utc::now());
+        let all_entries = entries
+            .into_iter()
+            .map(|e| Rc::new(HistEntryWrapper::new(e, source_width)))
+            .collect_vec();
         let mut app = App {
+            all_entries,
+            prefilter: Box::new(prefilter),
+            entries: Vec::new(),
             filtered_entries: FilteredList::default(),
-            entries: entries
-                .into_iter()
-                .filter(|e| e.matches(&[], &exclude_re))
-                .map(|e| Rc::new(e.into()))
-                .collect_vec(),
-            case_mode,
+            settings,
+            ranker,
             search_input: Input::new(inital_search),
+            query: Query::match_all(settings.case_mode),
+            query_input: String::new(),
+            query_error: None,
+            preview_mode: PreviewMode::Hidden,
+            timeline: None,
+            marked: Vec::new(),
+            edit: None,
         };
-        app.do_filter();
+        app.do_dedup();
         app
     }
 
-    fn run(mut self) -> anyhow::Result<Option<HistEntry>> {
+    fn run(mut self) -> anyhow::Result<Vec<HistEntry>> {
         struct DropGuard;
         impl Drop for DropGuard {
             fn drop(&mut self) {
@@ -164,7 +319,7 @@ impl App<'_> {
             match ratatui::crossterm::event::read()? {
                 Event::Key(ev) if ev.kind == KeyEventKind::Press => match self.handle_key(ev) {
                     HandleKeyRes::Continue => (),
-                    HandleKeyRes::Return(maybe_entry) => return Ok(maybe_entry),
+                    HandleKeyRes::Return(selected) => return Ok(selected),
                 },
                 Event::Paste(_ev) => {}
                 _ => {}
@@ -172,44 +327,256 @@ impl App<'_> {
         }
     }
 
-    /// Conver the current value of the search_input widget into the Vec
-    /// of include regexes to filter on
-    fn get_include_regexes(&self) -> Vec<Regex> {
-        self.search_input
-            .value()
-            .split_ascii_whitespace()
-            .map(|word| {
-                // unwrap() is safe, since we are searching for the literal `word`
-                raw_pattern_to_regex(&regex::escape(word), self.case_mode).unwrap()
-            })
-            .collect_vec()
+    /// Deduplicate and prefilter `all_entries` according to the dedup mode, then
+    /// re-run the search
+    fn do_dedup(&mut self) {
+        // Only the entries that absorb duplicates are cloned by `make_mut`
+        self.entries = dedup_entries_by(
+            self.all_entries.clone(),
+            self.settings.dedup_mode,
+            |e| &e.orig,
+            |e| &mut Rc::make_mut(e).orig,
+        )
+        .into_iter()
+        .filter(|e| (self.prefilter)(&e.orig))
+        .collect_vec();
+        self.do_filter();
     }
 
-    /// Perform filtering: convert search input to regexes, filter history entries,
-    /// create a new `FilteredList` instance for rendering
+    /// Perform filtering: parse the search input as a query, filter history entries,
+    /// create a new `FilteredList` instance for rendering. In fuzzy mode the entries
+    /// are sorted by score, then by `sort_order`, with the best match last. If the
+    /// query is invalid (e.g., while a regex is still being typed), the error is
+    /// shown in the footer and the last valid query is used instead, or all entries
+    /// are shown if there was none.
     fn do_filter(&mut self) {
-        let include_re = self.get_include_regexes();
-        self.filtered_entries = FilteredList::new(
-            self.entries
-                .iter()
-                .filter(|e| e.matches(&include_re, &[]))
-                .cloned()
-                .collect_vec(),
-        )
+        let (case_mode, match_mode) = (self.settings.case_mode, self.settings.match_mode);
+        match Query::parse(self.search_input.value(), case_mode, match_mode) {
+            Ok(query) => {
+                self.query = query;
+                self.query_input = self.search_input.value().to_owned();
+                self.query_error = None;
+            }
+            Err(err) => {
+                log::debug!("Invalid query: {:#}", err);
+                self.query_error = Some(error_summary(&err));
+                // Parse the last valid query again, since a toggle may have changed
+                // the case or match mode, and the results must match the footer
+                self.query = Query::parse(&self.query_input, case_mode, match_mode)
+                    .unwrap_or_else(|_| Query::match_all(case_mode));
+            }
+        }
+        let query = &self.query;
+        let mut filtered = self
+            .entries
+            .iter()
+            .filter_map(|e| {
+                let m = query.find(&e.orig, true)?;
+                Some((m.score, e.clone(), m.highlights))
+            })
+            .collect_vec();
+        if self.settings.match_mode == MatchMode::Fuzzy {
+            // stable sort, so entries with the same score stay in history order
+            filtered.sort_by_key(|(score, _e, _ranges)| *score);
+        }
+        self.ranker.sort(
+            self.settings.sort_order,
+            &mut filtered,
+            |(_score, e, _ranges)| &e.orig,
+        );
+        let (entries, highlights) = filtered
+            .into_iter()
+            .map(|(_score, e, ranges)| (e, ranges))
+            .unzip();
+        self.filtered_entries = FilteredList::new(entries, highlights);
+    }
+
+    /// Cycle through exact, regex, and fuzzy matching
+    fn toggle_match_mode(&mut self) {
+        self.settings.match_mode = self.settings.match_mode.next();
+        self.do_filter();
+    }
+
+    /// Switch between case-sensitive and case-insensitive search
+    fn toggle_case_mode(&mut self) {
+        self.settings.case_mode = self.settings.case_mode.toggle();
+        self.do_filter();
+    }
+
+    /// Cycle through the dedup modes
+    fn toggle_dedup_mode(&mut self) {
+        self.settings.dedup_mode = self.settings.dedup_mode.next();
+        self.do_dedup();
+    }
+
+    /// Cycle through the sort orders
+    fn toggle_sort_order(&mut self) {
+        self.settings.sort_order = self.settings.sort_order.next();
+        self.do_filter();
+    }
+
+    /// Show the timeline of all entries, without dedup, prefilter, and search,
+    /// with the selected entry selected
+    fn open_timeline(&mut self) {
+        let entries = self.all_entries.clone();
+        let Some(pos) = self
+            .filtered_entries
+            .selected_entry()
+            .and_then(|entry| position_of(entry, &entries))
+        else {
+            return;
+        };
+        let highlights = vec![Vec::new(); entries.len()];
+        let mut timeline = FilteredList::new(entries, highlights);
+        timeline.state.select(Some(pos));
+        self.timeline = Some(timeline);
+    }
+
+    /// The list that is currently shown, the timeline or the search results
+    fn current_list(&self) -> &FilteredList<'a> {
+        self.timeline.as_ref().unwrap_or(&self.filtered_entries)
+    }
+
+    /// Mark or unmark the selected entry and move on to the next one (towards the
+    /// top of the list)
+    fn toggle_mark(&mut self) {
+        let Some(entry) = self.current_list().get_selected() else {
+            return;
+        };
+        match self.marked.iter().position(|e| e.seq == entry.seq) {
+            Some(idx) => {
+                self.marked.remove(idx);
+            }
+            None => self.marked.push(entry),
+        }
+        match self.timeline.as_mut() {
+            Some(timeline) => timeline.select_previous(),
+            None => self.filtered_entries.select_previous(),
+        }
+    }
+
+    /// The entries to return when Enter is pressed: the marked entries in history
+    /// order, or the selected entry if none are marked
+    fn accept(&self) -> Vec<HistEntry> {
+        if self.marked.is_empty() {
+            return self.current_list().get_selected().into_iter().collect();
+        }
+        self.marked
+            .iter()
+            .sorted_by_key(|e| (e.seq, e.ts))
+            .cloned()
+            .collect()
+    }
+
+    /// Handle a key event while the timeline is shown. Only navigation and
+    /// selection are possible, Esc or Ctrl-T go back to the search.
+    fn handle_timeline_key(&mut self, key: KeyEvent) -> HandleKeyRes {
+        let Some(timeline) = self.timeline.as_mut() else {
+            return HandleKeyRes::Continue;
+        };
+        match key.code {
+            KeyCode::Esc => self.timeline = None,
+            KeyCode::Char('t') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.timeline = None;
+            }
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return HandleKeyRes::Return(Vec::new());
+            }
+            KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.preview_mode = self.preview_mode.next();
+            }
+            KeyCode::Char('e') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.start_edit();
+            }
+            KeyCode::Enter => return HandleKeyRes::Return(self.accept()),
+            KeyCode::Tab => self.toggle_mark(),
+            KeyCode::Up => timeline.select_previous(),
+            KeyCode::Down => timeline.select_next(),
+            KeyCode::PageUp => timeline.scroll_up(),
+            KeyCode::PageDown => timeline.scroll_down(),
+            _ => (),
+        }
+        HandleKeyRes::Continue
+    }
+
+    /// Load the selected entry into the edit line
+    fn start_edit(&mut self) {
+        if let Some(entry) = self.current_list().get_selected() {
+            let input = Input::new(entry.command.clone());
+            self.edit = Some(Edit { entry, input });
+        }
+    }
+
+    /// Handle a key event while a command is edited. Enter accepts the edited
+    /// command, together with the marked entries, Esc cancels the edit
+    fn handle_edit_key(&mut self, key: KeyEvent) -> HandleKeyRes {
+        let Some(edit) = self.edit.as_mut() else {
+            return HandleKeyRes::Continue;
+        };
+        match key.code {
+            KeyCode::Esc => self.edit = None,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return HandleKeyRes::Return(Vec::new());
+            }
+            KeyCode::Enter => {
+                let mut entry = edit.entry.clone();
+                entry.command = edit.input.value().to_owned();
+                // The edited entry replaces the original if it was marked
+                let mut selected = self
+                    .marked
+                    .iter()
+                    .filter(|e| e.seq != entry.seq)
+                    .cloned()
+                    .collect_vec();
+                selected.push(entry);
+                selected.sort_by_key(|e| (e.seq, e.ts));
+                return HandleKeyRes::Return(selected);
+            }
+            _ => {
+                edit.input.handle_event(&Event::Key(key));
+            }
+        }
+        HandleKeyRes::Continue
     }
 
     /// Handle a key event
     fn handle_key(&mut self, key: KeyEvent) -> HandleKeyRes {
+        if self.edit.is_some() {
+            return self.handle_edit_key(key);
+        }
+        if self.timeline.is_some() {
+            return self.handle_timeline_key(key);
+        }
         match key.code {
             KeyCode::Esc => {
-                return HandleKeyRes::Return(None);
+                return HandleKeyRes::Return(Vec::new());
             }
             KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                return HandleKeyRes::Return(None);
+                return HandleKeyRes::Return(Vec::new());
             }
-            KeyCode::Enter => {
-                return HandleKeyRes::Return(self.filtered_entries.get_selected());
+            KeyCode::Char('f') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.toggle_match_mode();
             }
+            KeyCode::Char('o') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.toggle_sort_order();
+            }
+            KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.toggle_case_mode();
+            }
+            KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.toggle_dedup_mode();
+            }
+            KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.preview_mode = self.preview_mode.next();
+            }
+            KeyCode::Char('t') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.open_timeline();
+            }
+            KeyCode::Char('e') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.start_edit();
+            }
+            KeyCode::Enter => return HandleKeyRes::Return(self.accept()),
+            KeyCode::Tab => self.toggle_mark(),
             KeyCode::Up => self.filtered_entries.select_previous(),
             KeyCode::Down => self.filtered_entries.select_next(),
             KeyCode::PageUp => self.filtered_entries.scroll_up(),
@@ -227,76 +594,294 @@ impl App<'_> {
 
     /// Render the app screen
     fn render(&mut self, frame: &mut Frame) {
-        let [list_area, footer_area] =
+        let [main_area, footer_area] =
             Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
-        self.filtered_entries.render(list_area, frame);
+        let preview_layout = match self.preview_mode {
+            PreviewMode::Hidden => None,
+            PreviewMode::Bottom => Some(Layout::vertical([
+                Constraint::Fill(1),
+                Constraint::Percentage(40),
+            ])),
+            PreviewMode::Side => Some(Layout::horizontal([
+                Constraint::Percentage(50),
+                Constraint::Percentage(50),
+            ])),
+        };
+        let list_area = match preview_layout {
+            Some(layout) => {
+                let [list_area, preview_area] = layout.areas(main_area);
+                self.render_preview(preview_area, frame);
+                list_area
+            }
+            None => main_area,
+        };
+        let marked = self.marked.iter().map(|e| e.seq).collect();
+        match self.timeline.as_mut() {
+            Some(timeline) => timeline.render("Timeline", &marked, list_area, frame),
+            None => self.filtered_entries.render(
+                "Interactive History Search",
+                &marked,
+                list_area,
+                frame,
+            ),
+        }
         self.render_footer(footer_area, frame);
     }
 
-    // Render the footer area with prompt and search input
+    fn render_preview(&self, area: Rect, frame: &mut Frame) {
+        let block = Block::new()
+            .title(Line::raw("Preview").centered())
+            .borders(Borders::TOP)
+            .border_set(symbols::border::EMPTY)
+            .border_style(HEADER_FOOTER_STYLE);
+        let text = self
+            .current_list()
+            .selected_entry()
+            .map(|entry| self.preview_lines(entry))
+            .unwrap_or_default();
+        frame.render_widget(
+            Paragraph::new(text).block(block).wrap(Wrap { trim: false }),
+            area,
+        );
+    }
+
+    /// The lines of the preview for `entry`: its metadata, the full command, and the
+    /// commands run before and after it
+    fn preview_lines(&self, entry: &HistEntry) -> Vec<Line<'static>> {
+        let field = |label: &str, value: String| {
+            Line::from(vec![
+                Span::styled(format!("{:<10}", label), PREVIEW_LABEL_STYLE),
+                Span::raw(value),
+            ])
+        };
+        let mut lines = vec![field("Time:", entry.ts_as_string())];
+        if let Some(source) = &entry.source {
+            lines.push(field("Source:", source.to_string()));
+        }
+        if let Some(duration) = entry.duration {
+            lines.push(field("Duration:", format!("{}s", duration.num_seconds())));
+        }
+        lines.push(field("Count:", entry.count.to_string()));
+        if entry.count > 1 {
+            lines.push(field("First:", format_ts(entry.first_seen())));
+            lines.push(field("Last:", format_ts(entry.last_seen())));
+        }
+
+        lines.push(Line::default());
+        lines.extend(entry.command.lines().map(|l| Line::raw(l.to_owned())));
+
+        if let Some(pos) = position_of(entry, &self.all_entries) {
+            lines.push(Line::default());
+            let range = pos.saturating_sub(PREVIEW_CONTEXT)
+                ..(pos + PREVIEW_CONTEXT + 1).min(self.all_entries.len());
+            for (idx, neighbor) in self.all_entries[range.clone()].iter().enumerate() {
+                let neighbor = &neighbor.orig;
+                let style = if range.start + idx == pos {
+                    PREVIEW_LABEL_STYLE
+                } else {
+                    PREVIEW_CONTEXT_STYLE
+                };
+                lines.push(Line::styled(
+                    format!(
+                        "{} {}",
+                        neighbor.ts_as_string(),
+                        neighbor.command.replace('\n', " ↵ ")
+                    ),
+                    style,
+                ));
+            }
+        }
+        lines
+    }
+
+    // Render the footer area with prompt, search input, the query error (if any),
+    // and the current settings
     fn render_footer(&mut self, area: Rect, frame: &mut Frame) {
-        let [prompt_area, input_area] =
-            Layout::horizontal([Constraint::Length(1), Constraint::Min(1)]).areas(area);
+        if let Some(edit) = &self.edit {
+            let mode = " [edit, Enter to accept, Esc to cancel] ";
+            let [prompt_area, input_area, mode_area] = Layout::horizontal([
+                Constraint::Length(1),
+                Constraint::Min(1),
+                Constraint::Length(mode.len() as u16),
+            ])
+            .areas(area);
+            frame.render_widget(Paragraph::new("$").style(HEADER_FOOTER_STYLE), prompt_area);
+            render_input(&edit.input, input_area, frame);
+            frame.render_widget(Paragraph::new(mode).style(HEADER_FOOTER_STYLE), mode_area);
+            return;
+        }
+        let mode = format!(
+            " {}{}[{}] [{}] [{}] [{}] ",
+            if self.timeline.is_some() {
+                "[timeline, Esc to go back] "
+            } else {
+                ""
+            },
+            if self.marked.is_empty() {
+                String::new()
+            } else {
+                format!("[{} marked] ", self.marked.len())
+            },
+            self.settings.match_mode.name(),
+            self.settings.case_mode.name(),
+            self.settings.dedup_mode.name(),
+            self.settings.sort_order.name()
+        );
+        let error = self
+            .query_error
+            .as_ref()
+            .map(|err| format!(" {} ", err))
+            .unwrap_or_default();
+        // Leave at least half of the footer for the search input
+        let error_width = (error.chars().count() as u16).min(area.width / 2);
+        let [prompt_area, input_area, error_area, mode_area] = Layout::horizontal([
+            Constraint::Length(1),
+            Constraint::Min(1),
+            Constraint::Length(error_width),
+            Constraint::Length(mode.len() as u16),
+        ])
+        .areas(area);
 
         frame.render_widget(Paragraph::new(">").style(HEADER_FOOTER_STYLE), prompt_area);
-        self.render_search_input(input_area, frame);
+        render_input(&self.search_input, input_area, frame);
+        frame.render_widget(Paragraph::new(error).style(ERROR_STYLE), error_area);
+        frame.render_widget(Paragraph::new(mode).style(HEADER_FOOTER_STYLE), mode_area);
     }
+}
 
-    fn render_search_input(&mut self, area: Rect, frame: &mut Frame) {
-        // allow one character for the cursor
-        let width = area.width.saturating_sub(1);
-        let scroll = self.search_input.visual_scroll(width as usize);
-        frame.render_widget(
-            Paragraph::new(self.search_input.value())
-                .style(HEADER_FOOTER_STYLE)
-                .scroll((0, scroll as u16)),
-            area,
-        );
-        // Ratatui hides the cursor unless it's explicitly set. Position the  cursor past the
-        // end of the input text
-        let x = self.search_input.visual_cursor().saturating_sub(scroll);
-        frame.set_cursor_position((area.x + x as u16, area.y))
+/// Render a single line text input. Newlines (in edited multi-line commands)
+/// are shown as `↵`, which has the same width, so the cursor position still fits
+fn render_input(input: &Input, area: Rect, frame: &mut Frame) {
+    // allow one character for the cursor
+    let width = area.width.saturating_sub(1);
+    let scroll = input.visual_scroll(width as usize);
+    frame.render_widget(
+        Paragraph::new(input.value().replace('\n', "↵"))
+            .style(HEADER_FOOTER_STYLE)
+            .scroll((0, scroll as u16)),
+        area,
+    );
+    // Ratatui hides the cursor unless it's explicitly set. Position the  cursor past the
+    // end of the input text
+    let x = input.visual_cursor().saturating_sub(scroll);
+    frame.set_cursor_position((area.x + x as u16, area.y))
+}
+
+/// Condense `err` into a single line for the footer. Regex parse errors span
+/// several lines (the pattern, a caret and the message), we only keep the message.
+fn error_summary(err: &anyhow::Error) -> String {
+    err.chain()
+        .filter_map(|cause| {
+            let msg = cause.to_string();
+            msg.lines()
+                .map(str::trim)
+                .rfind(|line| !line.is_empty())
+                .map(|line| line.trim_start_matches("error: ").to_owned())
+        })
+        .join(": ")
+}
+
+/// The index of `entry` in `entries`, a subsequence of the merged history
+fn position_of(entry: &HistEntry, entries: &[Rc<HistEntryWrapper>]) -> Option<usize> {
+    let is_same =
+        |e: &Rc<HistEntryWrapper>| e.orig.ts == entry.ts && e.orig.command == entry.command;
+    // `seq` is the index in the merged history, so `entries` is sorted by it, unless
+    // the entries didn't come from `open_and_parse_history_files`
+    match entries.binary_search_by_key(&entry.seq, |e| e.orig.seq) {
+        Ok(idx) if is_same(&entries[idx]) => Some(idx),
+        _ => entries.iter().position(is_same),
     }
 }
 
 /// Wraps a HistoryEntry so it's easier to use as a ratatui `ListItem`
+#[derive(Clone)]
 struct HistEntryWrapper<'a> {
     orig: HistEntry,
+    /// The timestamp (and source) we render in front of the command
+    prefix: String,
     /// The ratatui `Line`s that we will use to render this entry in the list
     lines: Vec<Line<'a>>,
 }
 
 impl HistEntryWrapper<'_> {
-    pub fn matches(&self, include_re: &[Regex], exclude_re: &[Regex]) -> bool {
-        self.orig.matches(include_re, exclude_re)
+    /// Wrap `entry`. If `source_width` is set, the name of the history file the
+    /// entry came from is shown in a column of that width before the timestamp.
+    fn new(entry: HistEntry, source_width: Option<usize>) -> Self {
+        let prefix = match source_width {
+            Some(width) => format!(
+                "{:width$} {}",
+                entry.source_name().unwrap_or_default(),
+                entry.ts_as_string()
+            ),
+            None => entry.ts_as_string(),
+        };
+        let lines = build_lines(&prefix, &entry.command, &[]);
+        Self {
+            orig: entry,
+            prefix,
+            lines,
+        }
+    }
+
+    /// Like `lines` but with the given byte ranges of the command highlighted
+    fn highlighted_lines(&self, highlights: &[Range<usize>]) -> Vec<Line<'static>> {
+        build_lines(&self.prefix, &self.orig.command, highlights)
     }
 }
 
-impl From<HistEntry> for HistEntryWrapper<'_> {
-    fn from(entry: HistEntry) -> Self {
-        // We render each entry as the timestamp, followed by the command. For
-        // multiline entries, we indent sub-sequent lines so that line is aligned
-        // with the command from the first line.
-        let ts_str = entry.ts_as_string();
-        let indent = ts_str.len() + 1;
-        let indent_spaces = std::iter::repeat_n(' ', indent).collect::<String>();
-        let mut lines = Vec::new();
-        for line in entry.command.lines() {
-            let mut s = String::with_capacity(indent + line.len());
-            if lines.is_empty() {
-                // first line
-                s += &ts_str;
-                s += " ";
-                s += line;
-            } else {
-                // subsequent lines
-                s += &indent_spaces;
-                s += line;
+/// Build the ratatui `Line`s for rendering a command.
+///
+/// We render each entry as the `prefix` (the timestamp), followed by the command. For
+/// multiline entries, we indent sub-sequent lines so that line is aligned
+/// with the command from the first line. `highlights` are sorted, non-overlapping
+/// byte ranges of `command` that are rendered with `MATCH_STYLE`.
+fn build_lines(prefix: &str, command: &str, highlights: &[Range<usize>]) -> Vec<Line<'static>> {
+    let indent = prefix.len() + 1;
+    let indent_spaces = std::iter::repeat_n(' ', indent).collect::<String>();
+    let mut lines = Vec::new();
+    let mut highlights = highlights.iter().peekable();
+    let mut line_start = 0;
+    for line in command.split_inclusive('\n') {
+        let line_range = line_start..line_start + line.trim_end_matches('\n').len();
+        line_start += line.len();
+
+        let mut spans = Vec::new();
+        if lines.is_empty() {
+            // first line
+            spans.push(Span::raw(format!("{} ", prefix)));
+        } else {
+            // subsequent lines
+            spans.push(Span::raw(indent_spaces.clone()));
+        }
+        let mut pos = line_range.start;
+        while let Some(hl) = highlights.peek() {
+            if hl.start >= line_range.end {
+                break;
             }
-            lines.push(Line::raw(s))
+            let hl_start = hl.start.max(pos);
+            let hl_end = hl.end.min(line_range.end);
+            if pos < hl_start {
+                spans.push(Span::raw(command[pos..hl_start].to_owned()));
+            }
+            if hl_start < hl_end {
+                spans.push(Span::styled(
+                    command[hl_start..hl_end].to_owned(),
+                    MATCH_STYLE,
+                ));
+            }
+            pos = hl_end.max(pos);
+            if hl.end > line_range.end {
+                // The highlight continues on the next line
+                break;
+            }
+            highlights.next();
         }
-        Self { orig: entry, lines }
+        if pos < line_range.end {
+            spans.push(Span::raw(command[pos..line_range.end].to_owned()));
+        }
+        lines.push(Line::from(spans))
     }
+    lines
 }
 
 #[cfg(test)]
@@ -305,26 +890,136 @@ mod test {
     use chrono::DateTime;
     use chrono::Duration;
     use chrono::Utc;
+    use regex::Regex;
 
     use super::*;
 
+    fn settings(case_mode: CaseMode, match_mode: MatchMode) -> Settings {
+        Settings {
+            case_mode,
+            match_mode,
+            sort_order: SortOrder::Time,
+            dedup_mode: DedupMode::None,
+        }
+    }
+
     fn newentry(ts: DateTime<Utc>, command: &str) -> HistEntry {
-        HistEntry {
-            ts,
-            command: command.to_owned(),
+        HistEntry::new(ts, command)
+    }
+
+    /// Set `seq` to the index in `entries`, like `open_and_parse_history_files` does
+    fn with_seq(mut entries: Vec<HistEntry>) -> Vec<HistEntry> {
+        for (seq, e) in entries.iter_mut().enumerate() {
+            e.seq = seq;
         }
+        entries
     }
 
     fn mk_entries() -> Vec<HistEntry> {
         let t0 = crate::default_ts() + Duration::hours(28);
         let mk_ts = |mins| t0 + Duration::minutes(mins);
-        vec![
+        with_seq(vec![
             newentry(t0, "Lorem Ipsum"),
             newentry(mk_ts(5), "is simply a dummy"),
             newentry(mk_ts(10), "text of the"),
             newentry(mk_ts(23), "printing and typesetting"),
             newentry(mk_ts(42), "industry"),
-        ]
+        ])
+    }
+
+    /// The commands of the search results
+    fn to_commands(app: &App) -> Vec<String> {
+        app.filtered_entries
+            .entries
+            .iter()
+            .map(|e| e.orig.command.clone())
+            .collect_vec()
+    }
+
+    #[test]
+    fn test_entry_wrapper_source() {
+        let mut entry = newentry(crate::default_ts(), "ls -la\nfoo");
+        let ts_str = entry.ts_as_string();
+        let wrapped = HistEntryWrapper::new(entry.clone(), None);
+        assert_eq!(wrapped.lines[0].to_string(), format!("{} ls -la", ts_str));
+
+        entry.source = Some(Rc::from("/tmp/hist"));
+        let wrapped = HistEntryWrapper::new(entry, Some(6));
+        assert_eq!(
+            wrapped.lines[0].to_string(),
+            format!("hist   {} ls -la", ts_str)
+        );
+        // continuation lines are aligned with the command
+        assert_eq!(
+            wrapped.lines[1].to_string(),
+            format!("{}foo", " ".repeat(7 + ts_str.len() + 1))
+        );
+    }
+
+    #[test]
+    fn test_build_lines() {
+        let to_strings = |lines: Vec<Line>| {
+            lines
+                .iter()
+                .map(|line| {
+                    line.spans
+                        .iter()
+                        .map(|span| {
+                            if span.style == MATCH_STYLE {
+                                format!("[{}]", span.content)
+                            } else {
+                                span.content.to_string()
+                            }
+                        })
+                        .join("")
+                })
+                .collect_vec()
+        };
+        assert_eq!(
+            to_strings(build_lines("TS", "ls -la\nfoo", &[])),
+            vec!["TS ls -la", "   foo"]
+        );
+        assert_eq!(
+            to_strings(build_lines("TS", "ls -la\nfoo", &[0..2, 4..5])),
+            vec!["TS [ls] -[l]a", "   foo"]
+        );
+        // highlights spanning multiple lines
+        assert_eq!(
+            to_strings(build_lines("TS", "ls -la\nfoo\nbar", &[5..9, 11..12])),
+            vec!["TS ls -l[a]", "   [fo]o", "   [b]ar"]
+        );
+        // empty lines
+        assert_eq!(
+            to_strings(build_lines("TS", "\nbar\n", &[1..2, 3..4])),
+            vec!["TS ", "   [b]a[r]"]
+        );
+    }
+
+    #[test]
+    fn test_app_highlights() {
+        let app = App::new(
+            mk_entries(),
+            "ing t".to_owned(),
+            |_| true,
+            settings(CaseMode::Sensitive, MatchMode::Exact),
+            false,
+        );
+        // "printing and typesetting"
+        assert_eq!(app.filtered_entries.entries.len(), 1);
+        assert_eq!(
+            app.filtered_entries.highlights[0],
+            vec![4..8, 13..14, 19..24]
+        );
+
+        let app = App::new(
+            mk_entries(),
+            "ind".to_owned(),
+            |_| true,
+            settings(CaseMode::Sensitive, MatchMode::Fuzzy),
+            false,
+        );
+        assert_eq!(app.filtered_entries.entries.len(), 2);
+        assert_eq!(app.filtered_entries.highlights[1], vec![0..3]);
     }
 
     #[test]
@@ -333,7 +1028,13 @@ mod test {
             wrapped.iter().map(|e| e.orig.clone()).collect_vec()
         }
 
-        let app = App::new(mk_entries(), String::new(), Vec::new(), CaseMode::Sensitive);
+        let app = App::new(
+            mk_entries(),
+            String::new(),
+            |_| true,
+            settings(CaseMode::Sensitive, MatchMode::Exact),
+            false,
+        );
         assert_eq!(app.filtered_entries.entries.len(), 5);
         assert_eq!(to_orig(app.filtered_entries.entries), mk_entries());
 
@@ -341,8 +1042,9 @@ mod test {
         let app = App::new(
             mk_entries(),
             "Lorem".to_owned(),
-            Vec::new(),
-            CaseMode::Sensitive,
+            |_| true,
+            settings(CaseMode::Sensitive, MatchMode::Exact),
+            false,
         );
         assert_eq!(app.filtered_entries.entries.len(), 1);
         assert_eq!(to_orig(app.entries), mk_entries());
@@ -355,17 +1057,20 @@ mod test {
         let app = App::new(
             mk_entries(),
             "lorem".to_owned(),
-            Vec::new(),
-            CaseMode::Sensitive,
+            |_| true,
+            settings(CaseMode::Sensitive, MatchMode::Exact),
+            false,
         );
         assert_eq!(app.filtered_entries.entries.len(), 0);
 
-        // exclude filter
+        // prefilter
+        let exclude_re = vec![Regex::new("simply").unwrap()];
         let app = App::new(
             mk_entries(),
             String::new(),
-            vec![Regex::new("simply").unwrap()],
-            CaseMode::Sensitive,
+            move |e| e.matches(&[], &exclude_re),
+            settings(CaseMode::Sensitive, MatchMode::Exact),
+            false,
         );
         let orig = mk_entries();
         let expected = vec![
@@ -379,4 +1084,439 @@ mod test {
         assert_eq!(to_orig(app.entries), expected);
         assert_eq!(to_orig(app.filtered_entries.entries), expected);
     }
+
+    #[test]
+    fn test_app_fuzzy() {
+        let mut app = App::new(
+            mk_entries(),
+            "tt".to_owned(),
+            |_| true,
+            settings(CaseMode::Insensitive, MatchMode::Fuzzy),
+            false,
+        );
+        // Sorted by score, best match last
+        assert_eq!(
+            to_commands(&app),
+            vec!["printing and typesetting", "text of the"]
+        );
+
+        // Toggle to exact matching
+        app.handle_key(KeyEvent::new(KeyCode::Char('f'), KeyModifiers::CONTROL));
+        assert_eq!(app.settings.match_mode, MatchMode::Exact);
+        assert_eq!(to_commands(&app), vec!["printing and typesetting"]);
+        // Then to regex matching
+        app.search_input = Input::new("t+ [iy]".to_owned());
+        app.handle_key(KeyEvent::new(KeyCode::Char('f'), KeyModifiers::CONTROL));
+        assert_eq!(app.settings.match_mode, MatchMode::Regex);
+        assert_eq!(
+            to_commands(&app),
+            vec!["printing and typesetting", "industry"]
+        );
+        app.handle_key(KeyEvent::new(KeyCode::Char('f'), KeyModifiers::CONTROL));
+        assert_eq!(app.settings.match_mode, MatchMode::Fuzzy);
+    }
+
+    #[test]
+    fn test_app_toggles() {
+        let t0 = crate::default_ts() + Duration::days(100);
+        let entries = vec![
+            newentry(t0, "make"),
+            newentry(t0 + Duration::minutes(1), "Make"),
+            newentry(t0 + Duration::minutes(2), "make"),
+            newentry(t0 + Duration::minutes(3), "make"),
+            newentry(t0 + Duration::minutes(4), "ls"),
+        ];
+        let mut app = App::new(
+            entries,
+            "make".to_owned(),
+            |e| e.command != "ls",
+            Settings {
+                dedup_mode: DedupMode::Consecutive,
+                ..settings(CaseMode::Sensitive, MatchMode::Exact)
+            },
+            false,
+        );
+        assert_eq!(to_commands(&app), vec!["make", "make"]);
+
+        // Ctrl-S toggles case sensitivity
+        app.handle_key(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL));
+        assert_eq!(app.settings.case_mode, CaseMode::Insensitive);
+        assert_eq!(to_commands(&app), vec!["make", "Make", "make"]);
+        app.handle_key(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL));
+        assert_eq!(app.settings.case_mode, CaseMode::Sensitive);
+
+        // Ctrl-D cycles through the dedup modes, the prefilter still applies
+        let mut modes = Vec::new();
+        for _ in 0..4 {
+            app.handle_key(KeyEvent::new(KeyCode::Char('d'), KeyModifiers::CONTROL));
+            modes.push((app.settings.dedup_mode, to_commands(&app).len()));
+        }
+        assert_eq!(
+            modes,
+            vec![
+                (DedupMode::GlobalLast, 1),
+                (DedupMode::GlobalFirst, 1),
+                (DedupMode::None, 3),
+                (DedupMode::Consecutive, 2),
+            ]
+        );
+        assert!(app.entries.iter().all(|e| e.orig.command != "ls"));
+        // The retained entry absorbs its duplicates
+        app.handle_key(KeyEvent::new(KeyCode::Char('d'), KeyModifiers::CONTROL));
+        assert_eq!(app.filtered_entries.entries[0].orig.count, 3);
+        // Entries without duplicates are shared with `all_entries`, which is unchanged
+        assert!(Rc::ptr_eq(&app.entries[0], &app.all_entries[1]));
+        assert!(app.all_entries.iter().all(|e| e.orig.count == 1));
+    }
+
+    #[test]
+    fn test_app_sort_order() {
+        let t0 = crate::default_ts() + Duration::days(100);
+        let entries = vec![
+            newentry(t0, "make"),
+            newentry(t0 + Duration::minutes(1), "make test"),
+            newentry(t0 + Duration::minutes(2), "make"),
+            newentry(t0 + Duration::minutes(3), "ls"),
+        ];
+        let mut app = App::new(
+            entries,
+            "make".to_owned(),
+            |_| true,
+            settings(CaseMode::Sensitive, MatchMode::Exact),
+            false,
+        );
+        assert_eq!(to_commands(&app), vec!["make", "make test", "make"]);
+
+        // Ctrl-O cycles to frecency: the most frequent command comes last
+        app.handle_key(KeyEvent::new(KeyCode::Char('o'), KeyModifiers::CONTROL));
+        assert_eq!(app.settings.sort_order, SortOrder::Frecency);
+        assert_eq!(to_commands(&app), vec!["make test", "make", "make"]);
+        app.handle_key(KeyEvent::new(KeyCode::Char('o'), KeyModifiers::CONTROL));
+        assert_eq!(app.settings.sort_order, SortOrder::Count);
+        app.handle_key(KeyEvent::new(KeyCode::Char('o'), KeyModifiers::CONTROL));
+        assert_eq!(app.settings.sort_order, SortOrder::Time);
+    }
+
+    #[test]
+    fn test_app_regex() {
+        fn type_str(app: &mut App, s: &str) {
+            for c in s.chars() {
+                app.handle_key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+            }
+        }
+
+        let mut app = App::new(
+            mk_entries(),
+            "/^[a-z]+".to_owned(),
+            |_| true,
+            settings(CaseMode::Sensitive, MatchMode::Exact),
+            false,
+        );
+        // An unterminated regex is a plain word and matches nothing
+        assert_eq!(app.filtered_entries.entries.len(), 0);
+        assert_eq!(app.query_error, None);
+        type_str(&mut app, "y/");
+        assert_eq!(app.filtered_entries.entries.len(), 1);
+        assert_eq!(app.filtered_entries.highlights[0], vec![0..8]);
+
+        // While the regex is invalid, the error is shown and the previous results
+        // are kept
+        app.search_input = Input::new("/^[a-z]+y/ /(the/".to_owned());
+        app.do_filter();
+        assert_eq!(
+            app.query_error.as_deref(),
+            Some("Error parsing regex `/(the/`: unclosed group")
+        );
+        assert_eq!(app.filtered_entries.entries.len(), 1);
+        app.search_input = Input::new("/(the)/".to_owned());
+        app.do_filter();
+        assert_eq!(app.query_error, None);
+        assert_eq!(app.filtered_entries.entries.len(), 1);
+        assert_eq!(app.filtered_entries.entries[0].orig.command, "text of the");
+
+        // Toggles re-run the last valid query with the new settings
+        app.search_input = Input::new("Text".to_owned());
+        app.do_filter();
+        app.search_input = Input::new("Text /(the/".to_owned());
+        app.do_filter();
+        assert_eq!(app.filtered_entries.entries.len(), 0);
+        app.handle_key(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL));
+        assert_eq!(app.settings.case_mode, CaseMode::Insensitive);
+        assert_eq!(app.filtered_entries.entries.len(), 1);
+        assert_eq!(app.filtered_entries.entries[0].orig.command, "text of the");
+        assert!(app.query_error.is_some());
+
+        // An invalid initial query shows all entries
+        let app = App::new(
+            mk_entries(),
+            "/(the/".to_owned(),
+            |_| true,
+            settings(CaseMode::Sensitive, MatchMode::Exact),
+            false,
+        );
+        assert!(app.query_error.is_some());
+        assert_eq!(app.filtered_entries.entries.len(), 5);
+    }
+
+    #[test]
+    fn test_app_negation() {
+        let t0 = crate::default_ts() + Duration::days(100);
+        let entries = vec![
+            newentry(t0, "docker run --rm alpine"),
+            newentry(t0 + Duration::minutes(1), "docker run -it ubuntu"),
+            newentry(t0 + Duration::minutes(2), "docker ps"),
+        ];
+        let mut app = App::new(
+            entries,
+            "docker run".to_owned(),
+            |_| true,
+            settings(CaseMode::Sensitive, MatchMode::Exact),
+            false,
+        );
+        assert_eq!(to_commands(&app).len(), 2);
+        for c in " !--rm".chars() {
+            app.handle_key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+        assert_eq!(to_commands(&app), vec!["docker run -it ubuntu"]);
+        // negated terms are not highlighted
+        assert_eq!(app.filtered_entries.highlights[0], vec![0..6, 7..10]);
+
+        app.search_input = Input::new("docker -ubuntu".to_owned());
+        app.do_filter();
+        assert_eq!(
+            to_commands(&app),
+            vec!["docker run --rm alpine", "docker ps"]
+        );
+    }
+
+    #[test]
+    fn test_app_preview() {
+        fn to_strings(lines: Vec<Line>) -> Vec<String> {
+            lines.iter().map(|l| l.to_string()).collect_vec()
+        }
+
+        let mut entries = mk_entries();
+        entries[1].command = "is simply\na dummy".to_owned();
+        entries[1].source = Some("/tmp/hist".into());
+        entries.push(newentry(entries[1].ts, "is simply\na dummy"));
+        let entries = with_seq(entries);
+        let mut app = App::new(
+            entries.clone(),
+            "dummy".to_owned(),
+            |_| true,
+            Settings {
+                dedup_mode: DedupMode::GlobalFirst,
+                ..settings(CaseMode::Sensitive, MatchMode::Exact)
+            },
+            false,
+        );
+        assert_eq!(app.preview_mode, PreviewMode::Hidden);
+        app.handle_key(KeyEvent::new(KeyCode::Char('p'), KeyModifiers::CONTROL));
+        assert_eq!(app.preview_mode, PreviewMode::Bottom);
+
+        let selected = app.filtered_entries.selected_entry().unwrap();
+        let ts = |idx: usize| entries[idx].ts_as_string();
+        assert_eq!(
+            to_strings(app.preview_lines(selected)),
+            vec![
+                format!("Time:     {}", ts(1)),
+                "Source:   /tmp/hist".to_owned(),
+                "Count:    2".to_owned(),
+                format!("First:    {}", ts(1)),
+                format!("Last:     {}", ts(1)),
+                String::new(),
+                "is simply".to_owned(),
+                "a dummy".to_owned(),
+                String::new(),
+                format!("{} Lorem Ipsum", ts(0)),
+                format!("{} is simply ↵ a dummy", ts(1)),
+                format!("{} text of the", ts(2)),
+                format!("{} printing and typesetting", ts(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_app_timeline() {
+        fn selected_command(app: &App) -> String {
+            app.current_list().selected_entry().unwrap().command.clone()
+        }
+
+        let mut app = App::new(
+            mk_entries(),
+            "t".to_owned(),
+            |e| !e.command.starts_with("Lorem"),
+            settings(CaseMode::Sensitive, MatchMode::Exact),
+            false,
+        );
+        app.filtered_entries.state.select(Some(1));
+        let selected = app.filtered_entries.state.selected();
+        assert_eq!(selected_command(&app), "printing and typesetting");
+
+        // The timeline shows all entries, also the ones the prefilter removes, with
+        // the selected entry selected
+        app.handle_key(KeyEvent::new(KeyCode::Char('t'), KeyModifiers::CONTROL));
+        let timeline = app.timeline.as_ref().unwrap();
+        assert_eq!(timeline.entries.len(), 5);
+        assert_eq!(timeline.state.selected(), Some(3));
+        assert_eq!(selected_command(&app), "printing and typesetting");
+        app.handle_key(KeyEvent::new(KeyCode::Up, KeyModifiers::NONE));
+        assert_eq!(selected_command(&app), "text of the");
+        // Typing doesn't change the search
+        app.handle_key(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE));
+        assert_eq!(app.search_input.value(), "t");
+
+        // Going back restores the search results and selection
+        app.handle_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        assert!(app.timeline.is_none());
+        assert_eq!(app.filtered_entries.state.selected(), selected);
+        assert_eq!(selected_command(&app), "printing and typesetting");
+
+        // Entries from the timeline can be selected, also ones that were filtered
+        app.handle_key(KeyEvent::new(KeyCode::Char('t'), KeyModifiers::CONTROL));
+        for _ in 0..4 {
+            app.handle_key(KeyEvent::new(KeyCode::Up, KeyModifiers::NONE));
+        }
+        match app.handle_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)) {
+            HandleKeyRes::Return(selected) => {
+                assert_eq!(selected, vec![app.all_entries[0].orig.clone()])
+            }
+            _ => panic!("Expected the selected entry"),
+        }
+    }
+
+    #[test]
+    fn test_app_multi_select() {
+        fn accept(app: &mut App) -> Vec<String> {
+            match app.handle_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)) {
+                HandleKeyRes::Return(selected) => selected.into_iter().map(|e| e.command).collect(),
+                HandleKeyRes::Continue => panic!("Expected Enter to return"),
+            }
+        }
+        let tab = KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE);
+
+        let mut app = App::new(
+            mk_entries(),
+            String::new(),
+            |_| true,
+            settings(CaseMode::Sensitive, MatchMode::Exact),
+            false,
+        );
+        // Without marks, the selected entry is returned
+        app.filtered_entries.state.select(Some(4));
+        assert_eq!(accept(&mut app), vec!["industry"]);
+
+        // Tab marks the entry and moves up
+        app.handle_key(tab);
+        assert_eq!(app.filtered_entries.state.selected(), Some(3));
+        app.handle_key(tab);
+        app.handle_key(tab);
+        // Tab again unmarks
+        app.filtered_entries.state.select(Some(3));
+        app.handle_key(tab);
+        // Marks persist across searches
+        app.search_input = Input::new("Lorem".to_owned());
+        app.do_filter();
+        app.filtered_entries.state.select(Some(0));
+        app.handle_key(tab);
+        assert_eq!(app.marked.len(), 3);
+        assert_eq!(
+            accept(&mut app),
+            vec!["Lorem Ipsum", "text of the", "industry"]
+        );
+
+        // Entries with the same command and timestamp have the same ID, but are
+        // marked separately
+        let entries = with_seq(vec![
+            newentry(crate::default_ts(), "make"),
+            newentry(crate::default_ts(), "make"),
+        ]);
+        let mut app = App::new(
+            entries,
+            String::new(),
+            |_| true,
+            Settings {
+                dedup_mode: DedupMode::None,
+                ..settings(CaseMode::Sensitive, MatchMode::Exact)
+            },
+            false,
+        );
+        app.filtered_entries.state.select(Some(1));
+        app.handle_key(tab);
+        assert_eq!(app.marked.len(), 1);
+        app.handle_key(tab);
+        assert_eq!(app.marked.len(), 2);
+        assert_eq!(accept(&mut app), vec!["make", "make"]);
+    }
+
+    #[test]
+    fn test_multi_join() {
+        let entries = vec![
+            newentry(crate::default_ts(), "cd src"),
+            newentry(crate::default_ts(), "make"),
+        ];
+        assert_eq!(MultiJoin::And.join(&entries), "cd src && make");
+        assert_eq!(MultiJoin::Newline.join(&entries), "cd src\nmake");
+        assert_eq!(
+            MultiJoin::Script.join(&entries),
+            "#!/usr/bin/env bash\nset -e\n\ncd src\nmake\n"
+        );
+        assert_eq!(MultiJoin::And.join(&entries[..1]), "cd src");
+    }
+
+    #[test]
+    fn test_app_edit() {
+        fn key(app: &mut App, code: KeyCode, modifiers: KeyModifiers) -> Option<Vec<String>> {
+            match app.handle_key(KeyEvent::new(code, modifiers)) {
+                HandleKeyRes::Return(selected) => {
+                    Some(selected.into_iter().map(|e| e.command).collect())
+                }
+                HandleKeyRes::Continue => None,
+            }
+        }
+
+        let mut app = App::new(
+            mk_entries(),
+            String::new(),
+            |_| true,
+            settings(CaseMode::Sensitive, MatchMode::Exact),
+            false,
+        );
+        app.filtered_entries.state.select(Some(2));
+        key(&mut app, KeyCode::Char('e'), KeyModifiers::CONTROL);
+        assert_eq!(app.edit.as_ref().unwrap().input.value(), "text of the");
+        // Typing edits the command, not the search
+        for c in " end".chars() {
+            key(&mut app, KeyCode::Char(c), KeyModifiers::NONE);
+        }
+        assert_eq!(app.search_input.value(), "");
+        assert_eq!(app.filtered_entries.entries.len(), 5);
+        // Esc cancels the edit, but not the selection
+        assert_eq!(key(&mut app, KeyCode::Esc, KeyModifiers::NONE), None);
+        assert!(app.edit.is_none());
+
+        key(&mut app, KeyCode::Char('e'), KeyModifiers::CONTROL);
+        key(&mut app, KeyCode::Backspace, KeyModifiers::NONE);
+        key(&mut app, KeyCode::Char('m'), KeyModifiers::NONE);
+        assert_eq!(
+            key(&mut app, KeyCode::Enter, KeyModifiers::NONE),
+            Some(vec!["text of thm".to_owned()])
+        );
+
+        // With marked entries, the edited entry replaces the original
+        app.edit = None;
+        app.filtered_entries.state.select(Some(4));
+        key(&mut app, KeyCode::Tab, KeyModifiers::NONE);
+        key(&mut app, KeyCode::Tab, KeyModifiers::NONE);
+        key(&mut app, KeyCode::Down, KeyModifiers::NONE);
+        key(&mut app, KeyCode::Char('e'), KeyModifiers::CONTROL);
+        key(&mut app, KeyCode::Char('!'), KeyModifiers::NONE);
+        assert_eq!(
+            key(&mut app, KeyCode::Enter, KeyModifiers::NONE),
+            Some(vec![
+                "printing and typesetting!".to_owned(),
+                "industry".to_owned()
+            ])
+        );
+    }
 }
@@ -1,22 +1,55 @@
 use std::io::Write;
-use std::num::ParseIntError;
+use std::ops::Range;
 
 use anyhow::Context;
 use anyhow::anyhow;
 use base64::prelude::BASE64_STANDARD;
 use chrono::DateTime;
 use chrono::Utc;
+use histfile::DedupMode;
+use histfile::FileFormat;
+use histfile::HistEntry;
 use histfile::dedup_entries;
-use histfile::open_and_parse_history_file;
+use histfile::default_fish_history_file;
+use histfile::expand_histfile_args;
+use histfile::find_entry_by_id;
+use histfile::id_abbrev_len;
+use histfile::max_source_name_len;
+use histfile::open_and_parse_history_files;
+use interactive::MultiJoin;
+use interactive::Settings;
 use interactive::run_interactive;
 use itertools::Itertools as _;
+use output::ColorChoice;
+use output::EntryFormatter;
+use output::OutputFormat;
+use output::OutputLine;
+use output::add_context;
+use output::entry_to_json;
+use query::Query;
+use rank::Ranker;
+use rank::SortOrder;
 use ratatui::crossterm::tty::IsTty as _;
 use regex::Regex;
 use regex::RegexBuilder;
+use shell::CommandFilter;
+use stats::Stats;
+use stats::StatsArgs;
 use stderrlog::LogLevelNum;
+use template::Template;
+use timerange::TimeRange;
+use timerange::parse_time_arg;
 
+mod fuzzy;
 mod histfile;
 mod interactive;
+mod output;
+mod query;
+mod rank;
+mod shell;
+mod stats;
+mod template;
+mod timerange;
 
 /// Assume any "timestamps" we parse before that date are not actually
 /// valid.
@@ -27,14 +60,20 @@ fn default_ts() -> DateTime<Utc> {
     DateTime::from_timestamp(MIN_REASONABLE_UNIXTIME, 0).unwrap()
 }
 
-fn parse_hex_to_usize(s: &str) -> Result<usize, ParseIntError> {
-    usize::from_str_radix(s, 16)
+/// clap value parser for entry IDs (or prefixes thereof) given to `--copy`
+fn parse_id_prefix(s: &str) -> anyhow::Result<String> {
+    if s.is_empty() || s.len() > 16 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("`{}` is not a valid entry ID", s));
+    }
+    Ok(s.to_ascii_lowercase())
 }
 
-/// History Grep (hgr) -- A simple tool for searching through (bash) command
-/// history files.
+/// History Grep (hgr) -- A simple tool for searching through (bash, zsh, or
+/// fish) command history files.
 ///
 /// The output is similar to bash's `history`: `<ID> <DATE> <COMMAND>`
+/// The ID is derived from the timestamp and the command of an entry, so it does
+/// not change when the history file grows.
 /// WARNING: The ID is different from the one bash uses/produces and as such
 /// it must not be used with `!` history expansion.
 #[derive(clap::Parser)]
@@ -44,49 +83,134 @@ pub struct Args {
     #[arg(short, long, action=clap::ArgAction::Count)]
     debug: u8,
 
-    /// The history file to read. Default is $HISTFILE
-    #[arg(short = 'f', long)]
-    histfile: Option<String>,
-
-    /// If set, do *not* de-duplicate repeated commands
-    #[arg(long)]
+    /// The history file(s) to read. Default is $HISTFILE, or fish's history file
+    /// if $HISTFILE is not set.
+    ///
+    /// Can be given multiple times and accepts globs (e.g., `~/.bash_history.d/*`)
+    /// and directories (all files in the directory are read). The entries from all
+    /// files are merged by timestamp. If more than one file is read, the name of the
+    /// file an entry came from is shown next to it.
+    #[arg(short = 'f', long, action=clap::ArgAction::Append, global = true)]
+    histfile: Vec<String>,
+
+    /// The format of the history file. `auto` detects the format from the
+    /// beginning of the file
+    #[arg(long, value_enum, default_value_t = FileFormat::Auto, global = true)]
+    format: FileFormat,
+
+    /// How to remove duplicate commands. The global modes keep a single entry
+    /// for each command. In interactive mode, Ctrl-D cycles through the modes.
+    #[arg(long, value_enum, default_value_t = DedupMode::Consecutive)]
+    dedup: DedupMode,
+
+    /// If set, do *not* de-duplicate repeated commands. Same as `--dedup=none`
+    #[arg(long, conflicts_with = "dedup")]
     no_dedup: bool,
 
     /// Gets the history entry with `ID` from the history file, prints it, and
-    /// copies it to the clipboard.
-    #[arg(long, visible_alias = "cp", value_name = "ID", value_parser = parse_hex_to_usize)]
-    copy: Option<usize>,
+    /// copies it to the clipboard. Like git's abbreviated hashes, any unique
+    /// prefix of an ID can be used.
+    #[arg(long, visible_alias = "cp", value_name = "ID", value_parser = parse_id_prefix)]
+    copy: Option<String>,
 
     /// Run interactive mode.
     ///
     /// Interactive mode allows interactive filtering and selection of a history entry.
-    /// Type search terms separated by spaces (see `patterns`), however, no regexes are
-    /// supported at this time. The list of history entries will be interactively filtered.
-    /// Use arrow keys and PgUp/PgDown to navigate. Esc to quit, Enter to select an entry.
+    /// Type a search query (see `patterns`), including `/regex/` terms and `-word`
+    /// or `!word` to exclude entries. The list of history entries will be
+    /// interactively filtered. While the query is invalid,
+    /// e.g., an incomplete regex, the error is shown in the footer and the results
+    /// of the last valid query are shown (all entries if there is none). Use arrow keys and PgUp/PgDown to navigate. Esc to quit,
+    /// Enter to select an entry. Ctrl-F cycles through exact, regex (each word is a
+    /// regex), and fuzzy matching, Ctrl-S toggles case sensitivity, Ctrl-D cycles
+    /// through the dedup modes (see `--dedup`), and Ctrl-O cycles through the sort
+    /// orders (see `--sort`). The footer shows the current settings. Ctrl-P shows a
+    /// preview of the selected entry at the bottom or side, with its metadata, the
+    /// full command, and the commands run around it. Ctrl-T opens the timeline of the
+    /// whole history around the selected entry, without dedup, search, and filters
+    /// like `--since` or `--exclude`. Any entry there can be selected. Esc
+    /// goes back to the search. Tab marks entries, Enter then selects all marked
+    /// entries in history order (see `--multi-join`). Ctrl-E loads the selected entry
+    /// into an edit line, where the command can be changed before it is accepted
+    /// with Enter.
     ///
-    /// [PATTERNS] are used as the initial search terms (again, no regex support though)
+    /// [PATTERNS] are used as the initial search query
     ///
-    /// The selected entry will be printed and copied to the clipboard
+    /// The selected (or edited) entries will be printed and copied to the clipboard
     #[arg(short = 'i', long, conflicts_with = "copy")]
     interactive: bool,
 
     /// For integration with bash's `bind -x` readline support.
     ///
     /// hgr is started in interactive mode and the search term(s) are seeded
-    /// with the contents of the `READLINE_LINE` env variable. If an entry is selected
+    /// with the words of the `READLINE_LINE` env variable, which are matched literally
+    /// (words like `-la` are quoted). If an entry is selected
     /// its written to the file `TMPFILE`. The selected entry is *not* written to
     /// stdout nor copied to the clipboard.
     #[arg(long, conflicts_with = "copy", value_name = "TMPFILE")]
     bash_readline_mode: Option<String>,
 
-    /// Use case-sensitive search. Default is non-sensitive
+    /// How to combine the commands when several entries are marked with Tab in
+    /// interactive mode
+    #[arg(long, value_enum, default_value_t = MultiJoin::And, conflicts_with = "copy")]
+    multi_join: MultiJoin,
+
+    /// Use fuzzy matching (in the style of fzf). Each word of the query matches if
+    /// its characters appear in the command in the same order. Matches are sorted by
+    /// the quality of the match, best matches last. Quoted phrases and `/regex/`
+    /// terms are still matched exactly. In interactive mode, Ctrl-F cycles through the
+    /// match modes.
+    #[arg(long, conflicts_with = "copy")]
+    fuzzy: bool,
+
+    /// Use case-sensitive search. Default is non-sensitive. In interactive mode,
+    /// Ctrl-S toggles case sensitivity
     #[arg(short = 's', long, conflicts_with = "copy")]
     case_sensitive: bool,
 
+    /// How to order the matching entries, best entries last. `count` and
+    /// `frecency` use how often a command occurs in the whole history, also
+    /// when duplicates are removed. With `--fuzzy`, `time` orders by match
+    /// quality. In interactive mode, Ctrl-O toggles the order.
+    #[arg(long, value_enum, default_value_t = SortOrder::Time, conflicts_with = "copy")]
+    sort: SortOrder,
+
+    /// Only show entries run at or after this time.
+    ///
+    /// Accepts absolute dates and times (`2026-10-01`, `2026-10-01T14:00`), which are
+    /// interpreted as local time, and relative times (`3d`, `2 weeks ago`, `yesterday`).
+    /// Entries without a timestamp are excluded if `--since` or `--until` is used.
+    #[arg(long, value_name = "TIME", value_parser = parse_time_arg, conflicts_with = "copy")]
+    since: Option<DateTime<Utc>>,
+
+    /// Only show entries run at or before this time. See `--since` for the format.
+    /// Note that a date without a time means midnight at the *start* of that day
+    #[arg(long, value_name = "TIME", value_parser = parse_time_arg, conflicts_with = "copy")]
+    until: Option<DateTime<Utc>>,
+
     /// Exclude commands matching these patterns.
     #[arg(short = 'v', long, action=clap::ArgAction::Append, conflicts_with = "copy")]
     exclude: Vec<String>,
 
+    /// Only show commands that run PROGRAM, e.g., `--program git` matches
+    /// `cd foo && git pull` but not `cd ~/src/git`. Commands are split like bash
+    /// does, at pipes, `&&`, `;`, etc. Can be given multiple times to match any of
+    /// the programs
+    #[arg(long, value_name = "PROGRAM", action=clap::ArgAction::Append, conflicts_with = "copy")]
+    program: Vec<String>,
+
+    /// Only show commands that have ARG as an argument (a whole word, after quotes
+    /// are removed). With `--program`, ARG must be an argument of that program.
+    /// Can be given multiple times, all arguments must match
+    #[arg(
+        long,
+        value_name = "ARG",
+        action=clap::ArgAction::Append,
+        allow_hyphen_values = true,
+        conflicts_with = "copy"
+    )]
+    arg: Vec<String>,
+
     /// Only display the last N *matching* entries (default is to show only as many entries
     /// the height of the current terminal on TTYs)
     #[arg(short = 'n', long, value_name = "N", conflicts_with_all = ["copy", "interactive", "show_all"])]
@@ -97,14 +221,126 @@ pub struct Args {
     #[arg(short = 'a', long, conflicts_with_all = ["copy", "interactive", "tail"])]
     show_all: bool,
 
-    /// The patterns to search for.
+    /// Also show N entries after each match. The context is taken from the history
+    /// *before* duplicates were removed. Context entries have `-` instead of spaces
+    /// between the columns, and groups of entries that are not adjacent are
+    /// separated by `--`. Not available with `--only-command` and `--template`, since
+    /// context could not be told apart from matches there
+    #[arg(
+        short = 'A',
+        long,
+        value_name = "N",
+        conflicts_with_all = ["copy", "interactive", "only_command", "template"]
+    )]
+    after_context: Option<usize>,
+
+    /// Also show N entries before each match. See `--after-context`
+    #[arg(
+        short = 'B',
+        long,
+        value_name = "N",
+        conflicts_with_all = ["copy", "interactive", "only_command", "template"]
+    )]
+    before_context: Option<usize>,
+
+    /// Also show N entries before and after each match. See `--after-context`
+    #[arg(
+        short = 'C',
+        long,
+        value_name = "N",
+        conflicts_with_all = ["copy", "interactive", "only_command", "template"]
+    )]
+    context: Option<usize>,
+
+    /// When to use colors: highlight matches and dim the ID and date columns.
+    /// `auto` uses colors if stdout is a TTY
+    #[arg(
+        long,
+        value_enum,
+        value_name = "WHEN",
+        default_value_t = ColorChoice::Auto,
+        conflicts_with_all = ["copy", "interactive"]
+    )]
+    color: ColorChoice,
+
+    /// The output format. `json` prints a single JSON array, `ndjson` prints one
+    /// JSON object per line. The objects contain the `id`, the timestamp `ts` (RFC 3339,
+    /// `null` if the entry has no timestamp), the `command`, its `count`, and, if known,
+    /// the `source` file, the `duration` in seconds and the `paths` the command used.
+    /// Entries shown with `--context` have `"context": true`
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        conflicts_with_all = ["copy", "interactive"]
+    )]
+    output: OutputFormat,
+
+    /// Print each matching entry using this template instead of the default
+    /// `<ID> <DATE>  <COMMAND>` format, e.g., `'{id}\t{ts:%H:%M}\t{cmd:oneline}'`.
+    ///
+    /// Placeholders: `{id}`, `{ts}` or `{ts:<strftime format>}`, `{cmd}`, `{cmd:quoted}`
+    /// (quoted for the shell), `{cmd:escaped}` (newlines, tabs, and backslashes escaped),
+    /// `{cmd:oneline}` (lines joined with ` ↵ `), `{source}`, `{duration}`, `{paths}`,
+    /// `{count}` (how often the command was run, see `--dedup`), and `{first_seen}` and
+    /// `{last_seen}` (when it was first and last run, with an optional format like `{ts}`).
+    /// Use `{{` and `}}` for literal braces. `\t`, `\n`, `\0`, and `\\` are replaced by
+    /// tab, newline, NUL, and backslash.
+    #[arg(
+        long,
+        value_parser = Template::parse,
+        conflicts_with_all = ["copy", "interactive", "output"]
+    )]
+    template: Option<Template>,
+
+    /// End each printed entry with a NUL character instead of a newline. Use this
+    /// to safely pipe multi-line commands to `xargs -0`, `fzf --read0`, etc.
+    #[arg(
+        short = 'z',
+        long,
+        conflicts_with_all = ["copy", "interactive", "output"]
+    )]
+    null: bool,
+
+    /// Only print the commands, without ID and date
+    #[arg(long, conflicts_with_all = ["copy", "interactive", "output", "template"])]
+    only_command: bool,
+
+    /// The search query. All patterns must match.
     ///
-    /// The patterns can appear in the command in any order. hgr searches
-    /// for an exact match. A regular expression pattern can be specified
-    /// by enclosing a term in slashes, e.g., `/foo[Bb]ar/`. Additional
-    /// slashes inside the pattern are allowed.
+    /// A pattern is matched literally and as a whole, e.g., `'git commit'` matches
+    /// the phrase `git commit`, unless it contains query syntax. Patterns can appear
+    /// in the command in any order. hgr searches for an exact match. A regular
+    /// expression can be specified by enclosing a term in slashes, e.g.,
+    /// `/foo[Bb]ar/`. Additional slashes inside the pattern are allowed.
+    ///
+    /// `OR` separates alternatives, `!term` excludes commands that match the term,
+    /// and parentheses group terms, e.g., `git '(push OR pull)' '!force'`. Unlike in
+    /// interactive mode, `-term` is matched literally, so a pasted command such as
+    /// `'git commit -m "wip"'` matches itself. Patterns starting with `-` must come
+    /// after `--`, e.g., `-- -la`.
+    /// Qualified terms restrict other fields: `after:TIME` and `before:TIME` (see
+    /// `--since`), `cmd:PROGRAM` (see `--program`), `arg:ARG` (see `--arg`), and
+    /// `file:TEXT` (the path of the history file contains TEXT).
     #[arg(conflicts_with = "copy")]
     patterns: Vec<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Show statistics about the history: the number of commands, the most used
+    /// programs and commands, activity by hour and day of week, and the longest
+    /// streaks of consecutive days with activity.
+    ///
+    /// Duplicates are never removed, since every run of a command counts.
+    /// `--since`, `--until`, `--exclude` (with `--case-sensitive`), `--program` and
+    /// `--arg` (given before `stats`) restrict the entries that are counted.
+    ///
+    /// To search for the word `stats` instead, use `hgr -- stats`.
+    Stats(StatsArgs),
 }
 
 pub fn actual_main(args: Args) -> Result<(), anyhow::Error> {
@@ -119,42 +355,62 @@ pub fn actual_main(args: Args) -> Result<(), anyhow::Error> {
         .init()
         .expect("Failed to setup logging");
 
-    let histfile = match args.histfile {
-        Some(histfile) => histfile,
-        None => match std::env::var("HISTFILE") {
-            Ok(histfile) => histfile,
-            Err(_) => {
-                return Err(anyhow::Error::msg(
-                    "No histfile argument given and no `HISTFILE` environment variable",
-                ));
-            }
-        },
+    let histfiles = if !args.histfile.is_empty() {
+        expand_histfile_args(&args.histfile)?
+    } else {
+        match std::env::var("HISTFILE") {
+            Ok(histfile) => vec![histfile],
+            Err(_) => match default_fish_history_file() {
+                Some(fish_histfile) => {
+                    log::debug!("No `HISTFILE`. Using fish history file");
+                    vec![fish_histfile.to_string_lossy().into_owned()]
+                }
+                None => {
+                    return Err(anyhow::Error::msg(
+                        "No histfile argument given and no `HISTFILE` environment variable",
+                    ));
+                }
+            },
+        }
     };
+    if histfiles.is_empty() {
+        return Err(anyhow!("No history files found"));
+    }
+    let show_source = histfiles.len() > 1;
 
-    let entries = open_and_parse_history_file(&histfile)?;
-    let entries = if args.no_dedup {
-        log::debug!("Read {} history entries", entries.len());
-        entries
-    } else {
-        let orig_len = entries.len();
-        let deduped = dedup_entries(entries);
-        log::debug!(
-            "Read {} history entries, {} entries after dedup",
-            orig_len,
-            deduped.len()
-        );
-        deduped
+    let entries = open_and_parse_history_files(&histfiles, args.format)?;
+    let command_filter = CommandFilter {
+        programs: args.program,
+        args: args.arg,
     };
 
-    if let Some(idx) = args.copy {
-        if idx >= entries.len() {
-            return Err(anyhow::anyhow!(
-                "No entry {:x} in history. Maximum entry id is {:x}",
-                idx,
-                entries.len()
-            ));
+    if let Some(Command::Stats(stats_args)) = args.command {
+        let time_range = TimeRange {
+            since: args.since,
+            until: args.until,
+        };
+        let case_mode = CaseMode::from_sensitive(args.case_sensitive);
+        let excl_patterns = process_magic_patterns(args.exclude, case_mode)?;
+        let entries = entries
+            .into_iter()
+            .filter(|e| {
+                time_range.contains(e)
+                    && e.matches(&[], &excl_patterns)
+                    && command_filter.matches(&e.command)
+            })
+            .collect_vec();
+        let stats = Stats::compute(&entries, stats_args.top, &chrono::Local);
+        if stats_args.json {
+            println!("{}", serde_json::to_string_pretty(&stats.to_json())?);
+        } else {
+            print!("{}", stats.to_table());
         }
-        let cmd = &entries[idx].command;
+        return Ok(());
+    }
+
+    if let Some(id_prefix) = args.copy {
+        let cmd = &find_entry_by_id(&entries, &id_prefix)?.command;
+        println!("{}", cmd);
         if std::io::stdout().is_tty() {
             std::io::stdout().write_all(&copy_to_clipboard_seq(cmd))?;
             println!("Copied to clipboard");
@@ -164,8 +420,43 @@ pub fn actual_main(args: Args) -> Result<(), anyhow::Error> {
         return Ok(());
     }
 
+    // Computed before dedup, so the length doesn't depend on the dedup mode
+    let id_len = id_abbrev_len(&entries);
+    let before_context = args.before_context.or(args.context).unwrap_or(0);
+    let after_context = args.after_context.or(args.context).unwrap_or(0);
+    // The context of matches is shown from the non-deduplicated history
+    let all_entries = if before_context > 0 || after_context > 0 {
+        entries.clone()
+    } else {
+        Vec::new()
+    };
+    let dedup_mode = if args.no_dedup {
+        DedupMode::None
+    } else {
+        args.dedup
+    };
+
+    let time_range = TimeRange {
+        since: args.since,
+        until: args.until,
+    };
     let case_mode = CaseMode::from_sensitive(args.case_sensitive);
+    let match_mode = MatchMode::from_fuzzy(args.fuzzy);
     let excl_patterns = process_magic_patterns(args.exclude, case_mode)?;
+    // The filters that are applied independent of the search query
+    let prefilter = |e: &HistEntry| {
+        time_range.contains(e)
+            && e.matches(&[], &excl_patterns)
+            && command_filter.matches(&e.command)
+    };
+    // Interactive mode deduplicates itself, since the dedup mode can be changed at
+    // runtime
+    let settings = Settings {
+        case_mode,
+        match_mode,
+        sort_order: args.sort,
+        dedup_mode,
+    };
 
     if let Some(output) = args.bash_readline_mode {
         log::debug!("Using bash_readline_mode output file `{}`", output);
@@ -175,31 +466,51 @@ pub fn actual_main(args: Args) -> Result<(), anyhow::Error> {
             .write(true)
             .open(&output)
             .with_context(|| format!("Opening bash-readline-mode output file `{}`", output))?;
-        let initial_search = std::env::var("READLINE_LINE").unwrap_or_default();
+        // The shell line is a (partial) command, not a query
+        let initial_search =
+            Query::literal_input(&std::env::var("READLINE_LINE").unwrap_or_default());
 
-        let selected = run_interactive(entries, initial_search.clone(), excl_patterns, case_mode)?;
+        let selected = run_interactive(entries, initial_search, prefilter, settings, show_source)?;
 
-        if let Some(selected) = selected {
-            log::debug!("Selected command is `{}`", selected.command);
-            fp.write_all(selected.command.as_bytes())?;
+        if !selected.is_empty() {
+            let command = args.multi_join.join(&selected);
+            log::debug!("Selected command is `{}`", command);
+            fp.write_all(command.as_bytes())?;
         }
     } else if args.interactive {
         if !std::io::stdout().is_tty() {
             return Err(anyhow!("stdout is not a TTY. Cannot use interactive mode"));
         }
-        let initial_search = args.patterns.join(" ");
-        let selected = run_interactive(entries, initial_search, excl_patterns, case_mode)?;
-        if let Some(selected) = selected {
-            println!("{}", selected.command);
-            std::io::stdout().write_all(&copy_to_clipboard_seq(&selected.command))?;
+        let initial_search = Query::input_from_args(&args.patterns);
+        let selected = run_interactive(entries, initial_search, prefilter, settings, show_source)?;
+        if !selected.is_empty() {
+            let command = args.multi_join.join(&selected);
+            println!("{}", command);
+            std::io::stdout().write_all(&copy_to_clipboard_seq(&command))?;
             println!("Copied to clipboard");
         }
     } else {
-        let inc_patterns = process_magic_patterns(args.patterns, case_mode)?;
-        let iter = entries
+        let query = Query::from_args(&args.patterns, case_mode, match_mode)
+            .context("Error parsing the search query")?;
+        log::debug!("Query is `{}`", query);
+        let orig_len = entries.len();
+        let entries = dedup_entries(entries, dedup_mode);
+        log::debug!(
+            "Read {} history entries, {} entries after dedup",
+            orig_len,
+            entries.len()
+        );
+        let mut matched = entries
             .iter()
-            .enumerate()
-            .filter(|(_idx, entry)| entry.matches(&inc_patterns, &excl_patterns));
+            .filter(|entry| prefilter(entry))
+            .filter_map(|entry| Some((query.find(entry, false)?.score, entry)))
+            .collect_vec();
+        if match_mode == MatchMode::Fuzzy {
+            // stable sort, so entries with the same score stay in history order
+            matched.sort_by_key(|(score, _entry)| *score);
+        }
+        Ranker::new(&entries, Utc::now()).sort(args.sort, &mut matched, |(_score, entry)| entry);
+        let iter = matched.into_iter().map(|(_score, entry)| entry);
         let tail = if std::io::stdout().is_tty() && !args.show_all {
             // we are on a TTY and `--show-all` wasn't used ==> only show as many entries
             // as fit the height of the terminal
@@ -212,13 +523,66 @@ pub fn actual_main(args: Args) -> Result<(), anyhow::Error> {
         }
         // unless `--tail` is explicitly specified
         .or(args.tail);
-        if let Some(tail) = tail {
-            for (idx, entry) in iter.tail(tail) {
-                println!("{:x} {}", idx, entry);
+        let formatter = EntryFormatter {
+            color: args.color.use_color(),
+            source_width: show_source.then(|| max_source_name_len(&entries)),
+            only_command: args.only_command,
+        };
+        let terminator = if args.null { '\0' } else { '\n' };
+        let print_entry = |id: &str, entry: &HistEntry| {
+            let highlights = if formatter.color {
+                query.highlights(entry)
+            } else {
+                Vec::new()
+            };
+            print!("{}{}", formatter.format(id, entry, &highlights), terminator);
+        };
+        let selected = match tail {
+            Some(tail) => iter.tail(tail).collect_vec(),
+            None => iter.collect_vec(),
+        };
+        let lines = add_context(&selected, &all_entries, before_context, after_context);
+        let line_to_json = |line: &OutputLine| match *line {
+            OutputLine::Match(entry) => Some(entry_to_json(&entry.short_id(id_len), entry)),
+            OutputLine::Context(entry) => {
+                let mut json = entry_to_json(&entry.short_id(id_len), entry);
+                json["context"] = true.into();
+                Some(json)
             }
-        } else {
-            for (idx, entry) in iter {
-                println!("{:x} {}", idx, entry);
+            OutputLine::Separator => None,
+        };
+        match args.output {
+            OutputFormat::Text => {
+                for line in lines {
+                    match (line, &args.template) {
+                        // Context conflicts with `--template`
+                        (OutputLine::Match(entry) | OutputLine::Context(entry), Some(template)) => {
+                            let id = entry.short_id(id_len);
+                            print!("{}{}", template.render(&id, entry), terminator)
+                        }
+                        (OutputLine::Match(entry), None) => {
+                            print_entry(&entry.short_id(id_len), entry)
+                        }
+                        (OutputLine::Context(entry), None) => print!(
+                            "{}{}",
+                            formatter.format_context(&entry.short_id(id_len), entry),
+                            terminator
+                        ),
+                        (OutputLine::Separator, _) => print!("--{}", terminator),
+                    }
+                }
+            }
+            OutputFormat::Json => {
+                let json = lines.iter().filter_map(line_to_json).collect();
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&serde_json::Value::Array(json))?
+                );
+            }
+            OutputFormat::Ndjson => {
+                for json in lines.iter().filter_map(line_to_json) {
+                    println!("{}", json);
+                }
             }
         }
     }
@@ -257,6 +621,61 @@ impl CaseMode {
             CaseMode::Insensitive
         }
     }
+
+    /// Switch between case-sensitive and case-insensitive
+    pub fn toggle(self) -> Self {
+        match self {
+            CaseMode::Sensitive => CaseMode::Insensitive,
+            CaseMode::Insensitive => CaseMode::Sensitive,
+        }
+    }
+
+    /// The name shown in the interactive mode footer
+    pub fn name(self) -> &'static str {
+        match self {
+            CaseMode::Sensitive => "case",
+            CaseMode::Insensitive => "nocase",
+        }
+    }
+}
+
+/// How search terms are matched against history entries
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MatchMode {
+    /// Terms must appear in the command exactly as given
+    Exact,
+    /// Each word is a regular expression. Only available in interactive mode
+    Regex,
+    /// Terms are matched as fuzzy subsequences and results sorted by score
+    Fuzzy,
+}
+
+impl MatchMode {
+    pub fn from_fuzzy(is_fuzzy: bool) -> Self {
+        if is_fuzzy {
+            MatchMode::Fuzzy
+        } else {
+            MatchMode::Exact
+        }
+    }
+
+    /// The next match mode, for toggling through them in interactive mode
+    pub fn next(self) -> Self {
+        match self {
+            MatchMode::Exact => MatchMode::Regex,
+            MatchMode::Regex => MatchMode::Fuzzy,
+            MatchMode::Fuzzy => MatchMode::Exact,
+        }
+    }
+
+    /// The name shown in the interactive mode footer
+    pub fn name(self) -> &'static str {
+        match self {
+            MatchMode::Exact => "exact",
+            MatchMode::Regex => "regex",
+            MatchMode::Fuzzy => "fuzzy",
+        }
+    }
 }
 
 /// Convert a pattern into a regex. See [`Args::pattern`]. If the given pattern
@@ -286,6 +705,19 @@ pub fn raw_pattern_to_regex(pattern: &str, case_mode: CaseMode) -> Result<Regex,
         .build()
 }
 
+/// Sort `ranges` and merge overlapping or adjacent ones
+pub fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_unstable_by_key(|r| r.start);
+    let mut ret: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match ret.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => ret.push(range),
+        }
+    }
+    ret
+}
+
 /// Convert a Vec of magic patterns to a Vec of regexes
 pub fn process_magic_patterns(
     magic_patterns: Vec<String>,
@@ -303,8 +735,60 @@ pub fn process_magic_patterns(
 
 #[cfg(test)]
 mod test {
+    use clap::CommandFactory as _;
+    use clap::Parser as _;
+
     use super::*;
 
+    #[test]
+    fn test_args() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn test_patterns_args() {
+        let query = |args: &[&str]| {
+            let args =
+                Args::try_parse_from(std::iter::once("hgr").chain(args.iter().copied())).unwrap();
+            Query::from_args(&args.patterns, CaseMode::Sensitive, MatchMode::Exact)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            query(&["git", "(push OR pull)", "!force"]),
+            "(and /git/ (or /push/ /pull/) (not /force/))"
+        );
+        assert_eq!(
+            query(&["git", "(push OR pull) -force"]),
+            "(and /git/ (and (or /push/ /pull/) /\\-force/))"
+        );
+        assert_eq!(query(&["--", "git", "-la"]), "(and /git/ /\\-la/)");
+    }
+
+    #[test]
+    fn test_stats_args() {
+        let args = Args::try_parse_from(["hgr", "-s", "stats"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Stats(_))));
+        assert!(args.patterns.is_empty());
+        // `--` searches for the word
+        let args = Args::try_parse_from(["hgr", "--", "stats"]).unwrap();
+        assert!(args.command.is_none());
+        assert_eq!(args.patterns, vec!["stats"]);
+    }
+
+    #[test]
+    fn test_context_args() {
+        let parse = |args: &[&str]| {
+            Args::try_parse_from(std::iter::once("hgr").chain(args.iter().copied()))
+        };
+        assert_eq!(parse(&["-C", "2", "git"]).unwrap().context, Some(2));
+        // Context lines could not be told apart from matches
+        for flag in ["-A", "-B", "-C"] {
+            assert!(parse(&[flag, "2", "--only-command", "git"]).is_err());
+            assert!(parse(&[flag, "2", "--template", "{cmd}", "git"]).is_err());
+        }
+    }
+
     #[test]
     fn test_string_to_regex() {
         // Exact match
@@ -363,4 +847,12 @@ mod test {
         assert!(re.is_match("aSDf"));
         assert!(re.is_match("asdf"));
     }
+
+    #[test]
+    fn test_merge_ranges() {
+        assert!(merge_ranges(vec![]).is_empty());
+        assert_eq!(merge_ranges(vec![5..7, 0..2]), vec![0..2, 5..7]);
+        assert_eq!(merge_ranges(vec![0..2, 2..4, 3..5, 7..8]), vec![0..5, 7..8]);
+        assert_eq!(merge_ranges(vec![0..10, 2..4]), vec![0..10]);
+    }
 }
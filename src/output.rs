@@ -0,0 +1,386 @@
+use std::ops::Range;
+
+use chrono::DateTime;
+use chrono::SecondsFormat;
+use chrono::Utc;
+use ratatui::crossterm::tty::IsTty as _;
+use serde_json::json;
+
+use crate::default_ts;
+use crate::histfile::HistEntry;
+
+/// ANSI sequence for highlighting matches (bold red, like grep)
+const MATCH_COLOR: &str = "\x1b[1;31m";
+/// ANSI sequence for the ID, source and timestamp columns
+const DIM_COLOR: &str = "\x1b[2m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// The format of the (non-interactive) output
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// `<ID> <DATE>  <COMMAND>`
+    Text,
+    /// A JSON array of entries
+    Json,
+    /// One JSON object per line
+    Ndjson,
+}
+
+/// When to use colors in the output
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Use colors if stdout is a TTY, `NO_COLOR` is not set and `TERM` is not `dumb`
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Check if colors should be used when writing to stdout
+    pub fn use_color(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::io::stdout().is_tty()
+                    && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && std::env::var("TERM").is_ok_and(|term| term != "dumb")
+            }
+        }
+    }
+}
+
+/// Formats history entries for the (non-interactive) output
+pub struct EntryFormatter {
+    /// Highlight matches and dim the other columns
+    pub color: bool,
+    /// If set, show the name of the history file an entry came from in a column
+    /// of this width
+    pub source_width: Option<usize>,
+    /// Only print the command, without ID, source, and date
+    pub only_command: bool,
+}
+
+impl EntryFormatter {
+    /// Format an entry as `<ID> [<SOURCE>] <DATE>  <COMMAND>` (or just `<COMMAND>`
+    /// if `only_command` is set). `highlights` are the sorted, non-overlapping byte
+    /// ranges of the command that matched the search terms. They are only used if
+    /// colors are enabled.
+    pub fn format(&self, id: &str, entry: &HistEntry, highlights: &[Range<usize>]) -> String {
+        if self.only_command {
+            return if self.color {
+                highlight(&entry.command, highlights)
+            } else {
+                entry.command.clone()
+            };
+        }
+        let mut columns = format!("{} ", id);
+        if let Some(width) = self.source_width {
+            columns += &format!("{:width$} ", entry.source_name().unwrap_or_default());
+        }
+        columns += &entry.ts_as_string();
+        if !self.color {
+            return format!("{}  {}", columns, entry.command);
+        }
+        format!(
+            "{}{}{}  {}",
+            DIM_COLOR,
+            columns,
+            RESET_COLOR,
+            highlight(&entry.command, highlights)
+        )
+    }
+
+    /// Format an entry that is only shown as context of a match. Like grep,
+    /// the columns are separated by `-` instead of spaces, so context can be
+    /// told apart from matches. With colors, the whole entry is dimmed.
+    /// `only_command` is ignored, since context conflicts with it.
+    pub fn format_context(&self, id: &str, entry: &HistEntry) -> String {
+        let mut columns = format!("{}-", id);
+        if let Some(width) = self.source_width {
+            columns += &format!("{:width$}-", entry.source_name().unwrap_or_default());
+        }
+        let line = format!("{}{}- {}", columns, entry.ts_as_string(), entry.command);
+        if self.color {
+            format!(
+                "{}{}{}",
+                DIM_COLOR,
+                line.replace('\n', &format!("{}\n{}", RESET_COLOR, DIM_COLOR)),
+                RESET_COLOR
+            )
+        } else {
+            line
+        }
+    }
+}
+
+/// A line of the (non-interactive) output
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputLine<'a> {
+    /// An entry that matched the search
+    Match(&'a HistEntry),
+    /// An entry that is only shown as context of a match
+    Context(&'a HistEntry),
+    /// Separates groups of matches and context that are not adjacent
+    Separator,
+}
+
+/// Add up to `before` and `after` context entries around each of `matches`,
+/// like `grep -B` and `grep -A` do. The context is taken from `all_entries`, the
+/// non-deduplicated history that `HistEntry::seq` refers to. Overlapping
+/// groups are merged, other groups are separated by `OutputLine::Separator`.
+pub fn add_context<'a>(
+    matches: &[&'a HistEntry],
+    all_entries: &'a [HistEntry],
+    before: usize,
+    after: usize,
+) -> Vec<OutputLine<'a>> {
+    if before == 0 && after == 0 {
+        return matches.iter().map(|m| OutputLine::Match(m)).collect();
+    }
+    let mut lines = Vec::new();
+    // The seq of the last entry we output
+    let mut prev_end: Option<usize> = None;
+    for (idx, m) in matches.iter().enumerate() {
+        let mut start = m.seq.saturating_sub(before);
+        match prev_end {
+            Some(prev_end) if prev_end < m.seq && start <= prev_end + 1 => start = prev_end + 1,
+            Some(_) => lines.push(OutputLine::Separator),
+            None => (),
+        }
+        lines.extend(all_entries[start..m.seq].iter().map(OutputLine::Context));
+        lines.push(OutputLine::Match(m));
+
+        let mut end = (m.seq + after).min(all_entries.len().saturating_sub(1));
+        // Don't print the next match as context
+        if let Some(next) = matches.get(idx + 1)
+            && next.seq > m.seq
+        {
+            end = end.min(next.seq - 1);
+        }
+        lines.extend(
+            all_entries[m.seq + 1..=end.max(m.seq)]
+                .iter()
+                .map(OutputLine::Context),
+        );
+        prev_end = Some(end.max(m.seq));
+    }
+    lines
+}
+
+/// Convert an entry into a JSON object for `--output json` and `--output ndjson`.
+/// Fields that are not known for the entry are omitted. The timestamp is `null`
+/// if the history file did not record it. `first_seen` and `last_seen` are only
+/// included if duplicates of the entry were removed.
+pub fn entry_to_json(id: &str, entry: &HistEntry) -> serde_json::Value {
+    let to_json_ts = |ts: DateTime<Utc>| {
+        (ts != default_ts()).then(|| ts.to_rfc3339_opts(SecondsFormat::Secs, true))
+    };
+    let mut obj = serde_json::Map::new();
+    obj.insert("id".to_owned(), json!(id));
+    obj.insert("ts".to_owned(), json!(to_json_ts(entry.ts)));
+    obj.insert("command".to_owned(), json!(entry.command));
+    obj.insert("count".to_owned(), json!(entry.count));
+    if entry.count > 1 {
+        obj.insert(
+            "first_seen".to_owned(),
+            json!(to_json_ts(entry.first_seen())),
+        );
+        obj.insert("last_seen".to_owned(), json!(to_json_ts(entry.last_seen())));
+    }
+    if let Some(source) = &entry.source {
+        obj.insert("source".to_owned(), json!(source.as_ref()));
+    }
+    if let Some(duration) = entry.duration {
+        obj.insert("duration".to_owned(), json!(duration.num_seconds()));
+    }
+    if !entry.paths.is_empty() {
+        obj.insert("paths".to_owned(), json!(entry.paths));
+    }
+    serde_json::Value::Object(obj)
+}
+
+/// Wrap the `highlights` ranges of `s` in `MATCH_COLOR`. Colors are reset at
+/// line breaks, so multi-line commands display correctly in pagers.
+fn highlight(s: &str, highlights: &[Range<usize>]) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut pos = 0;
+    for hl in highlights {
+        ret += &s[pos..hl.start];
+        ret += MATCH_COLOR;
+        ret += &s[hl.clone()].replace('\n', &format!("{}\n{}", RESET_COLOR, MATCH_COLOR));
+        ret += RESET_COLOR;
+        pos = hl.end;
+    }
+    ret += &s[pos..];
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use chrono::Duration;
+
+    use super::*;
+    use crate::histfile::DedupMode;
+    use crate::histfile::dedup_entries;
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(highlight("foo bar", &[]), "foo bar");
+        assert_eq!(
+            highlight("foo bar", &[0..1, 4..7]),
+            "\x1b[1;31mf\x1b[0moo \x1b[1;31mbar\x1b[0m"
+        );
+        assert_eq!(
+            highlight("foo\nbar", &[2..5, 6..7]),
+            "fo\x1b[1;31mo\x1b[0m\n\x1b[1;31mb\x1b[0ma\x1b[1;31mr\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_entry_to_json() {
+        let mut entry = HistEntry::new(DateTime::from_timestamp(1700000000, 0).unwrap(), "ls\n-la");
+        assert_eq!(
+            entry_to_json("2a", &entry).to_string(),
+            r#"{"id":"2a","ts":"2023-11-14T22:13:20Z","command":"ls\n-la","count":1}"#
+        );
+
+        entry.source = Some(Rc::from("/tmp/hist"));
+        entry.duration = Some(Duration::seconds(3));
+        entry.paths = vec!["foo".to_owned()];
+        assert_eq!(
+            entry_to_json("2a", &entry).to_string(),
+            r#"{"id":"2a","ts":"2023-11-14T22:13:20Z","command":"ls\n-la","count":1,"source":"/tmp/hist","duration":3,"paths":["foo"]}"#
+        );
+
+        // No timestamp
+        let entry = HistEntry::new(default_ts(), "ls");
+        assert_eq!(
+            entry_to_json("0", &entry).to_string(),
+            r#"{"id":"0","ts":null,"command":"ls","count":1}"#
+        );
+
+        // Duplicates were removed
+        let entries = dedup_entries(
+            vec![
+                HistEntry::new(DateTime::from_timestamp(1600000000, 0).unwrap(), "ls"),
+                HistEntry::new(DateTime::from_timestamp(1650000000, 0).unwrap(), "ls"),
+                HistEntry::new(DateTime::from_timestamp(1700000000, 0).unwrap(), "ls"),
+            ],
+            DedupMode::GlobalLast,
+        );
+        assert_eq!(
+            entry_to_json("2a", &entries[0]).to_string(),
+            r#"{"id":"2a","ts":"2023-11-14T22:13:20Z","command":"ls","count":3,"first_seen":"2020-09-13T12:26:40Z","last_seen":"2023-11-14T22:13:20Z"}"#
+        );
+    }
+
+    #[test]
+    fn test_format_entry() {
+        let mut entry = HistEntry::new(default_ts(), "ls -la");
+        let ts = entry.ts_as_string();
+        let formatter = EntryFormatter {
+            color: false,
+            source_width: None,
+            only_command: false,
+        };
+        assert_eq!(
+            formatter.format("1f", &entry, &[0..2, 4..5]),
+            format!("1f {}  ls -la", ts)
+        );
+
+        entry.source = Some(Rc::from("/tmp/hist"));
+        let mut formatter = EntryFormatter {
+            color: true,
+            source_width: Some(5),
+            only_command: false,
+        };
+        assert_eq!(
+            formatter.format("1f", &entry, &[0..2, 4..5]),
+            format!(
+                "\x1b[2m1f hist  {}\x1b[0m  \x1b[1;31mls\x1b[0m -\x1b[1;31ml\x1b[0ma",
+                ts
+            )
+        );
+
+        formatter.only_command = true;
+        assert_eq!(
+            formatter.format("1f", &entry, &[0..2, 4..5]),
+            "\x1b[1;31mls\x1b[0m -\x1b[1;31ml\x1b[0ma"
+        );
+        formatter.color = false;
+        assert_eq!(formatter.format("1f", &entry, &[0..2, 4..5]), "ls -la");
+    }
+
+    #[test]
+    fn test_format_context() {
+        let mut entry = HistEntry::new(default_ts(), "ls\n-la");
+        let ts = entry.ts_as_string();
+        let mut formatter = EntryFormatter {
+            color: false,
+            source_width: None,
+            only_command: false,
+        };
+        assert_eq!(
+            formatter.format_context("1f", &entry),
+            format!("1f-{}- ls\n-la", ts)
+        );
+
+        entry.source = Some(Rc::from("/tmp/hist"));
+        formatter.source_width = Some(5);
+        formatter.color = true;
+        assert_eq!(
+            formatter.format_context("1f", &entry),
+            format!("\x1b[2m1f-hist -{}- ls\x1b[0m\n\x1b[2m-la\x1b[0m", ts)
+        );
+    }
+
+    #[test]
+    fn test_add_context() {
+        let all = (0..10)
+            .map(|seq| {
+                let mut entry = HistEntry::new(default_ts(), format!("cmd {}", seq));
+                entry.seq = seq;
+                entry
+            })
+            .collect::<Vec<_>>();
+        let context = |matches: &[usize], before, after| {
+            let matches = matches.iter().map(|&seq| &all[seq]).collect::<Vec<_>>();
+            add_context(&matches, &all, before, after)
+                .into_iter()
+                .map(|line| match line {
+                    OutputLine::Match(e) => format!("{}", e.seq),
+                    OutputLine::Context(e) => format!("-{}", e.seq),
+                    OutputLine::Separator => "--".to_owned(),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(context(&[2, 7], 0, 0), vec!["2", "7"]);
+        assert_eq!(
+            context(&[2, 7], 1, 1),
+            vec!["-1", "2", "-3", "--", "-6", "7", "-8"]
+        );
+        // Adjacent and overlapping groups are merged
+        assert_eq!(
+            context(&[2, 5], 1, 1),
+            vec!["-1", "2", "-3", "-4", "5", "-6"]
+        );
+        assert_eq!(
+            context(&[2, 4], 0, 3),
+            vec!["2", "-3", "4", "-5", "-6", "-7"]
+        );
+        assert_eq!(context(&[2, 3], 2, 0), vec!["-0", "-1", "2", "3"]);
+        // Clamped at the start and end of the history
+        assert_eq!(
+            context(&[0, 9], 2, 2),
+            vec!["0", "-1", "-2", "--", "-7", "-8", "9"]
+        );
+        // Matches that are not in history order (e.g., with `--sort`)
+        assert_eq!(
+            context(&[5, 4], 1, 1),
+            vec!["-4", "5", "-6", "--", "-3", "4", "-5"]
+        );
+    }
+}
@@ -0,0 +1,901 @@
+use std::fmt::Display;
+use std::ops::Range;
+
+use anyhow::Context as _;
+use anyhow::bail;
+use itertools::Itertools;
+use regex::Regex;
+
+use crate::CaseMode;
+use crate::MatchMode;
+use crate::fuzzy::fuzzy_match;
+use crate::histfile::HistEntry;
+use crate::merge_ranges;
+use crate::raw_pattern_to_regex;
+use crate::shell::CommandFilter;
+use crate::timerange::TimeRange;
+use crate::timerange::parse_time_arg;
+
+/// A parsed search query. See [`Query::parse`] for the syntax
+#[derive(Clone, Debug)]
+pub struct Query {
+    /// `None` for the empty query, which matches everything
+    expr: Option<Expr>,
+    case_mode: CaseMode,
+}
+
+/// The AST of a query
+#[derive(Clone, Debug)]
+enum Expr {
+    Term(Term),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+/// A single search term
+#[derive(Clone, Debug)]
+enum Term {
+    /// A word, quoted phrase, or `/regex/` that must appear in the command
+    Text(Regex),
+    /// A word that must match the command fuzzily
+    Fuzzy(String),
+    /// `after:` and `before:`
+    Time(TimeRange),
+    /// `cmd:`, the command word of a simple command. The filter only has the
+    /// program set
+    Program(CommandFilter),
+    /// `arg:`, an argument of a simple command. The filter only has the argument
+    /// set
+    Arg(CommandFilter),
+    /// `file:`, the path of the history file the entry came from
+    File(Regex),
+}
+
+impl Query {
+    /// Parse a query. The syntax is:
+    /// * Words must appear in the command. In regex mode each word is a regex, in
+    ///   fuzzy mode they are matched fuzzily.
+    /// * `"quoted phrases"` must appear exactly, including whitespace. `\"` is a
+    ///   literal quote.
+    /// * `/regex/` must match the command. The regex can contain slashes and spaces,
+    ///   it ends at a slash that is followed by whitespace.
+    /// * Terms separated by whitespace must all match. Terms separated by `OR`
+    ///   are alternatives. Implicit AND binds stronger than `OR`.
+    /// * `-term` or `!term` negates a term. A `-` or `!` on its own is a word, as
+    ///   is a word starting with `--`. Use `!--rm` to exclude `--rm`. See
+    ///   [`Query::from_args`] for command line arguments, where only `!` negates.
+    /// * `(...)` groups terms, e.g., `git (push OR pull) -force`.
+    /// * Qualified terms:
+    ///   - `after:TIME` and `before:TIME`: the entry was run at or after/before
+    ///     `TIME` (see `--since` for the format, use quotes for spaces)
+    ///   - `cmd:PROGRAM`: the command runs `PROGRAM` (see `--program`)
+    ///   - `arg:ARG`: the command has the argument `ARG` (see `--arg`)
+    ///   - `file:TEXT`: the path of the history file contains `TEXT`
+    pub fn parse(input: &str, case_mode: CaseMode, match_mode: MatchMode) -> anyhow::Result<Self> {
+        let expr = Parser::new(input, case_mode, match_mode).parse()?;
+        Ok(Self { expr, case_mode })
+    }
+
+    /// The empty query, which matches everything
+    pub fn match_all(case_mode: CaseMode) -> Self {
+        Self {
+            expr: None,
+            case_mode,
+        }
+    }
+
+    /// Build a query from the command line arguments. All arguments must match.
+    /// An argument without query syntax (see `has_query_syntax`) is matched as a
+    /// whole, like a quoted phrase, so `'git commit'` and `-la` keep their literal
+    /// meaning. Other arguments are parsed like [`Query::parse`] does, except that
+    /// `-word` is a word, not a negation, so a pasted command such as
+    /// `git commit -m "wip"` matches itself. Use `!word` to negate.
+    pub fn from_args(
+        args: &[String],
+        case_mode: CaseMode,
+        match_mode: MatchMode,
+    ) -> anyhow::Result<Self> {
+        let mut exprs = Vec::new();
+        for arg in args.iter().filter(|arg| !arg.trim().is_empty()) {
+            if has_query_syntax(arg) {
+                let mut parser = Parser::new(arg, case_mode, match_mode);
+                parser.dash_negates = false;
+                exprs.extend(parser.parse()?);
+            } else {
+                let parser = Parser::new(arg, case_mode, match_mode);
+                exprs.push(Expr::Term(parser.literal_term(arg)?));
+            }
+        }
+        let expr = match exprs.len() {
+            0 => None,
+            1 => exprs.pop(),
+            _ => Some(Expr::And(exprs)),
+        };
+        Ok(Self { expr, case_mode })
+    }
+
+    /// The interactive search input that has the same meaning as
+    /// [`Query::from_args`]: literal arguments are quoted if needed, and so are
+    /// `-word`s in arguments with query syntax. Arguments that can't be parsed are
+    /// kept as they are.
+    pub fn input_from_args(args: &[String]) -> String {
+        args.iter()
+            .filter(|arg| !arg.trim().is_empty())
+            .map(|arg| {
+                if !has_query_syntax(arg) {
+                    return if is_plain_word(arg) {
+                        arg.clone()
+                    } else {
+                        quote(arg)
+                    };
+                }
+                let mut parser = Parser::new(arg, CaseMode::Sensitive, MatchMode::Exact);
+                parser.dash_negates = false;
+                if parser.parse().is_err() {
+                    return arg.clone();
+                }
+                let mut input = String::new();
+                let mut pos = 0;
+                for word in parser.dash_words {
+                    input += &arg[pos..word.start];
+                    input += &quote(&arg[word.clone()]);
+                    pos = word.end;
+                }
+                input + &arg[pos..]
+            })
+            .join(" ")
+    }
+
+    /// The interactive search input that matches each word of `text` literally,
+    /// e.g., for a partially typed shell command. Words that would be parsed as
+    /// query syntax are quoted.
+    pub fn literal_input(text: &str) -> String {
+        text.split_whitespace()
+            .map(|word| {
+                if is_plain_word(word) {
+                    word.to_owned()
+                } else {
+                    quote(word)
+                }
+            })
+            .join(" ")
+    }
+
+    /// Match `entry` against the query in a single pass. Returns `None` if it
+    /// doesn't match. The highlights are only computed if `highlight` is set
+    pub fn find(&self, entry: &HistEntry, highlight: bool) -> Option<QueryMatch> {
+        let Some(expr) = &self.expr else {
+            return Some(QueryMatch::default());
+        };
+        let mut ranges = Vec::new();
+        let score = expr.eval(entry, self.case_mode, highlight.then_some(&mut ranges))?;
+        Some(QueryMatch {
+            score,
+            highlights: merge_ranges(ranges),
+        })
+    }
+
+    /// The sorted, non-overlapping byte ranges of the command matched by the
+    /// (non-negated) text and fuzzy terms. Empty if `entry` doesn't match
+    pub fn highlights(&self, entry: &HistEntry) -> Vec<Range<usize>> {
+        self.find(entry, true)
+            .map(|m| m.highlights)
+            .unwrap_or_default()
+    }
+}
+
+/// How an entry matched a query
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct QueryMatch {
+    /// The fuzzy match score, the sum of the scores of the (non-negated) fuzzy
+    /// terms. Higher is better
+    pub score: i64,
+    /// The sorted, non-overlapping byte ranges of the command matched by the
+    /// (non-negated) text and fuzzy terms
+    pub highlights: Vec<Range<usize>>,
+}
+
+impl Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.expr {
+            Some(expr) => write!(f, "{}", expr),
+            None => write!(f, "(and)"),
+        }
+    }
+}
+
+impl Expr {
+    /// Match `entry` and return the fuzzy score, or `None` if it doesn't match. If
+    /// `ranges` is given, the highlighted ranges of the matching terms are added
+    /// to it. Of alternatives, all matching ones are highlighted and the best
+    /// score counts.
+    fn eval(
+        &self,
+        entry: &HistEntry,
+        case_mode: CaseMode,
+        mut ranges: Option<&mut Vec<Range<usize>>>,
+    ) -> Option<i64> {
+        match self {
+            Expr::Term(term) => term.eval(entry, case_mode, ranges),
+            Expr::Not(expr) => match expr.eval(entry, case_mode, None) {
+                Some(_) => None,
+                None => Some(0),
+            },
+            Expr::And(exprs) => exprs
+                .iter()
+                .map(|e| e.eval(entry, case_mode, ranges.as_deref_mut()))
+                .sum(),
+            Expr::Or(exprs) => {
+                let mut best = None;
+                for e in exprs {
+                    let len = ranges.as_ref().map_or(0, |r| r.len());
+                    match e.eval(entry, case_mode, ranges.as_deref_mut()) {
+                        Some(score) => best = best.max(Some(score)),
+                        // drop the ranges of a partially matching alternative
+                        None => {
+                            if let Some(ranges) = ranges.as_deref_mut() {
+                                ranges.truncate(len);
+                            }
+                        }
+                    }
+                }
+                best
+            }
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut list = |op: &str, exprs: &[Expr]| {
+            write!(f, "({}", op)?;
+            for e in exprs {
+                write!(f, " {}", e)?;
+            }
+            write!(f, ")")
+        };
+        match self {
+            Expr::Term(term) => write!(f, "{}", term),
+            Expr::Not(expr) => write!(f, "(not {})", expr),
+            Expr::And(exprs) => list("and", exprs),
+            Expr::Or(exprs) => list("or", exprs),
+        }
+    }
+}
+
+impl Term {
+    /// Like `Expr::eval` for a single term
+    fn eval(
+        &self,
+        entry: &HistEntry,
+        case_mode: CaseMode,
+        ranges: Option<&mut Vec<Range<usize>>>,
+    ) -> Option<i64> {
+        let is_match = match self {
+            Term::Text(re) => match ranges {
+                Some(ranges) => {
+                    let mut found = false;
+                    for m in re.find_iter(&entry.command) {
+                        found = true;
+                        if !m.is_empty() {
+                            ranges.push(m.range());
+                        }
+                    }
+                    found
+                }
+                None => re.is_match(&entry.command),
+            },
+            Term::Fuzzy(pattern) => {
+                let m = fuzzy_match(pattern, &entry.command, case_mode)?;
+                if let Some(ranges) = ranges {
+                    ranges.extend(m.ranges(&entry.command));
+                }
+                return Some(m.score);
+            }
+            Term::Time(range) => range.contains(entry),
+            Term::Program(filter) | Term::Arg(filter) => filter.matches(&entry.command),
+            Term::File(re) => entry.source.as_deref().is_some_and(|s| re.is_match(s)),
+        };
+        is_match.then_some(0)
+    }
+}
+
+impl Display for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Term::Text(re) => write!(f, "/{}/", re.as_str()),
+            Term::Fuzzy(pattern) => write!(f, "~{}", pattern),
+            Term::Time(range) => {
+                let fmt = |ts: Option<chrono::DateTime<chrono::Utc>>| {
+                    ts.map(|ts| ts.to_rfc3339()).unwrap_or_default()
+                };
+                write!(f, "time:{}..{}", fmt(range.since), fmt(range.until))
+            }
+            Term::Program(filter) => write!(f, "cmd:{}", filter.programs.join(" ")),
+            Term::Arg(filter) => write!(f, "arg:{}", filter.args.join(" ")),
+            Term::File(re) => write!(f, "file:/{}/", re.as_str()),
+        }
+    }
+}
+
+/// Check if a command line argument uses query syntax: `OR`, a word that starts
+/// with a group, a quote, `!` or a qualified term, or a `/regex/`. A leading `-`
+/// doesn't count, since it's more likely part of a command than a negation.
+fn has_query_syntax(arg: &str) -> bool {
+    let is_regex = |s: &str| s.len() > 1 && s.starts_with('/') && s.ends_with('/');
+    is_regex(arg.trim())
+        || arg.split_whitespace().any(|word| {
+            word == "OR"
+                || word.starts_with(['(', '"', '!'])
+                || is_regex(word)
+                || ["after:", "before:", "cmd:", "arg:", "file:"]
+                    .iter()
+                    .any(|field| word.starts_with(field))
+        })
+}
+
+/// Check if `text` is parsed as a single word, i.e., it has no whitespace and
+/// doesn't start with query syntax
+fn is_plain_word(text: &str) -> bool {
+    !text.contains(char::is_whitespace)
+        && !text.starts_with(['-', ')', '/'])
+        && !has_query_syntax(text)
+}
+
+/// Quote `text` as a phrase
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A recursive descent parser for queries
+struct Parser<'a> {
+    input: &'a str,
+    /// Byte offset of the next character
+    pos: usize,
+    /// How many groups are open
+    depth: usize,
+    case_mode: CaseMode,
+    match_mode: MatchMode,
+    /// Whether `-term` negates a term. Otherwise only `!term` does
+    dash_negates: bool,
+    /// The byte ranges of the `-word`s that were parsed as words because
+    /// `dash_negates` is not set
+    dash_words: Vec<Range<usize>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str, case_mode: CaseMode, match_mode: MatchMode) -> Self {
+        Self {
+            input,
+            pos: 0,
+            depth: 0,
+            case_mode,
+            match_mode,
+            dash_negates: true,
+            dash_words: Vec::new(),
+        }
+    }
+
+    /// Parse the whole input. `None` for an empty input
+    fn parse(&mut self) -> anyhow::Result<Option<Expr>> {
+        self.skip_whitespace();
+        if self.at_end() {
+            return Ok(None);
+        }
+        self.parse_or().map(Some)
+    }
+
+    fn rest(&self) -> &str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.input.len()
+    }
+
+    fn advance(&mut self, c: char) {
+        self.pos += c.len_utf8();
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Check if `c` ends a word (or a regex)
+    fn is_word_end(&self, c: Option<char>) -> bool {
+        match c {
+            None => true,
+            Some(')') => self.depth > 0,
+            Some(c) => c.is_whitespace(),
+        }
+    }
+
+    /// Check if the keyword `OR` comes next
+    fn at_or(&self) -> bool {
+        self.rest().strip_prefix("OR").is_some_and(|rest| {
+            let next = rest.chars().next();
+            self.is_word_end(next) || next == Some('(')
+        })
+    }
+
+    /// Consume the keyword `OR`, if it comes next
+    fn eat_or(&mut self) -> bool {
+        let at_or = self.at_or();
+        if at_or {
+            self.pos += 2;
+        }
+        at_or
+    }
+
+    /// `or := and ("OR" and)*`
+    fn parse_or(&mut self) -> anyhow::Result<Expr> {
+        let mut exprs = vec![self.parse_and()?];
+        while self.eat_or() {
+            self.skip_whitespace();
+            exprs.push(self.parse_and()?);
+        }
+        Ok(match exprs.len() {
+            1 => exprs.remove(0),
+            _ => Expr::Or(exprs),
+        })
+    }
+
+    /// `and := unary+`
+    fn parse_and(&mut self) -> anyhow::Result<Expr> {
+        let mut exprs = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => break,
+                Some(')') if self.depth > 0 => break,
+                Some(')') => bail!("Unmatched `)` at position {}", self.pos + 1),
+                _ if self.at_or() => break,
+                _ => exprs.push(self.parse_unary()?),
+            }
+        }
+        Ok(match exprs.len() {
+            0 => bail!("Expected a search term at position {}", self.pos + 1),
+            1 => exprs.remove(0),
+            _ => Expr::And(exprs),
+        })
+    }
+
+    /// `unary := ("-" | "!") atom | atom`
+    fn parse_unary(&mut self) -> anyhow::Result<Expr> {
+        match self.peek() {
+            Some(c @ ('-' | '!')) => {
+                let next = self.rest()[1..].chars().next();
+                // A lone `-` or `!` is a word, and so are long options like `--rm`
+                if self.is_word_end(next) || (c == '-' && next == Some('-')) {
+                    return self.parse_atom();
+                }
+                if c == '-' && !self.dash_negates {
+                    let start = self.pos;
+                    let expr = self.parse_atom()?;
+                    self.dash_words.push(start..self.pos);
+                    return Ok(expr);
+                }
+                self.advance(c);
+                Ok(Expr::Not(Box::new(self.parse_atom()?)))
+            }
+            _ => self.parse_atom(),
+        }
+    }
+
+    /// `atom := "(" or ")" | term`
+    fn parse_atom(&mut self) -> anyhow::Result<Expr> {
+        if self.peek() == Some('(') {
+            let start = self.pos;
+            self.advance('(');
+            self.depth += 1;
+            let expr = self.parse_or()?;
+            self.skip_whitespace();
+            if self.peek() != Some(')') {
+                bail!("Unclosed `(` at position {}", start + 1);
+            }
+            self.advance(')');
+            self.depth -= 1;
+            return Ok(expr);
+        }
+        self.parse_term().map(Expr::Term)
+    }
+
+    fn parse_term(&mut self) -> anyhow::Result<Term> {
+        for field in ["after", "before", "cmd", "arg", "file"] {
+            if let Some(rest) = self.rest().strip_prefix(field)
+                && rest.starts_with(':')
+            {
+                self.pos += field.len() + 1;
+                let value = match self.peek() {
+                    Some('"') => self.parse_phrase()?,
+                    _ => self.parse_word(),
+                };
+                if value.is_empty() {
+                    bail!("Missing value for `{}:`", field);
+                }
+                return self.field_term(field, &value);
+            }
+        }
+        match self.peek() {
+            Some('"') => {
+                let phrase = self.parse_phrase()?;
+                self.text_term(&regex::escape(&phrase))
+            }
+            Some('/') => match self.parse_regex() {
+                Some(re) => self
+                    .text_term(&re)
+                    .with_context(|| format!("Error parsing regex `/{}/`", re)),
+                None => self.word_term(),
+            },
+            _ => self.word_term(),
+        }
+    }
+
+    fn field_term(&self, field: &str, value: &str) -> anyhow::Result<Term> {
+        Ok(match field {
+            "after" => Term::Time(TimeRange {
+                since: Some(parse_time_arg(value)?),
+                until: None,
+            }),
+            "before" => Term::Time(TimeRange {
+                since: None,
+                until: Some(parse_time_arg(value)?),
+            }),
+            "cmd" => Term::Program(CommandFilter {
+                programs: vec![value.to_owned()],
+                args: Vec::new(),
+            }),
+            "arg" => Term::Arg(CommandFilter {
+                programs: Vec::new(),
+                args: vec![value.to_owned()],
+            }),
+            "file" => Term::File(raw_pattern_to_regex(&regex::escape(value), self.case_mode)?),
+            _ => unreachable!("Unknown field {}", field),
+        })
+    }
+
+    fn text_term(&self, pattern: &str) -> anyhow::Result<Term> {
+        Ok(Term::Text(raw_pattern_to_regex(pattern, self.case_mode)?))
+    }
+
+    fn word_term(&mut self) -> anyhow::Result<Term> {
+        let word = self.parse_word();
+        self.literal_term(&word)
+    }
+
+    /// A term for `text` without query syntax, according to the match mode
+    fn literal_term(&self, text: &str) -> anyhow::Result<Term> {
+        match self.match_mode {
+            MatchMode::Exact => self.text_term(&regex::escape(text)),
+            MatchMode::Regex => self
+                .text_term(text)
+                .with_context(|| format!("Error parsing regex `{}`", text)),
+            MatchMode::Fuzzy => Ok(Term::Fuzzy(text.to_owned())),
+        }
+    }
+
+    /// Read up to the end of the word
+    fn parse_word(&mut self) -> String {
+        let start = self.pos;
+        while let Some(c) = self.peek()
+            && !self.is_word_end(Some(c))
+        {
+            self.advance(c);
+        }
+        self.input[start..self.pos].to_owned()
+    }
+
+    /// Read a phrase in double quotes. `\"` and `\\` are escapes
+    fn parse_phrase(&mut self) -> anyhow::Result<String> {
+        let start = self.pos;
+        self.advance('"');
+        let mut phrase = String::new();
+        let mut chars = self.rest().chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos = self.input.len() - chars.as_str().len();
+                    return Ok(phrase);
+                }
+                '\\' => match chars.next() {
+                    Some(c @ ('"' | '\\')) => phrase.push(c),
+                    Some(c) => {
+                        phrase.push('\\');
+                        phrase.push(c);
+                    }
+                    None => phrase.push('\\'),
+                },
+                c => phrase.push(c),
+            }
+        }
+        bail!("Unclosed `\"` at position {}", start + 1)
+    }
+
+    /// Read a `/regex/` and return the regex, if there is a closing slash that
+    /// is followed by the end of a word. Otherwise, nothing is consumed.
+    fn parse_regex(&mut self) -> Option<String> {
+        let body = &self.rest()[1..];
+        let (end, _) = body
+            .match_indices('/')
+            .find(|(idx, _)| self.is_word_end(body[idx + 1..].chars().next()))?;
+        let re = body[..end].to_owned();
+        self.pos += end + 2;
+        Some(re)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use chrono::DateTime;
+
+    use super::*;
+
+    fn parse(input: &str) -> String {
+        Query::parse(input, CaseMode::Sensitive, MatchMode::Exact)
+            .unwrap()
+            .to_string()
+    }
+
+    fn matches(query: &str, command: &str) -> bool {
+        Query::parse(query, CaseMode::Insensitive, MatchMode::Exact)
+            .unwrap()
+            .find(&HistEntry::new(crate::default_ts(), command), false)
+            .is_some()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(""), "(and)");
+        assert_eq!(parse("  foo "), "/foo/");
+        assert_eq!(parse("foo bar"), "(and /foo/ /bar/)");
+        assert_eq!(parse("a b OR c"), "(or (and /a/ /b/) /c/)");
+        assert_eq!(parse("a (b OR c)"), "(and /a/ (or /b/ /c/))");
+        assert_eq!(parse("a OR(b c)"), "(or /a/ (and /b/ /c/))");
+        assert_eq!(parse("-a !b"), "(and (not /a/) (not /b/))");
+        assert_eq!(parse("-(a OR b)"), "(not (or /a/ /b/))");
+        assert_eq!(parse("- !"), "(and /\\-/ /!/)");
+        assert_eq!(parse("--rm !--rm"), "(and /\\-\\-rm/ (not /\\-\\-rm/))");
+        assert_eq!(parse("!-a --a"), "(and (not /\\-a/) /\\-\\-a/)");
+        assert_eq!(parse("ORx or"), "(and /ORx/ /or/)");
+        assert_eq!(
+            parse(r#""git  commit" "a\"b""#),
+            r#"(and /git  commit/ /a"b/)"#
+        );
+        assert_eq!(parse(r#"-"a b""#), "(not /a b/)");
+        assert_eq!(parse("/fo+ o/ /usr/bin"), "(and /fo+ o/ //usr/bin/)");
+        assert_eq!(parse("/abc/f[o]+.ar/"), "/abc/f[o]+.ar/");
+        assert_eq!(parse(r"(/a\)/)"), r"/a\)/");
+        assert_eq!(parse("(a) $(ls)"), "(and /a/ /\\$\\(ls\\)/)");
+        assert_eq!(
+            parse(r#"cmd:git arg:push file:"my hist""#),
+            "(and cmd:git arg:push file:/my hist/)"
+        );
+        assert_eq!(parse("cmdx:git"), "/cmdx:git/");
+        assert_eq!(
+            Query::parse("foo /ba+r/ \"x y\"", CaseMode::Sensitive, MatchMode::Fuzzy)
+                .unwrap()
+                .to_string(),
+            "(and ~foo /ba+r/ /x y/)"
+        );
+        assert_eq!(
+            Query::parse(r#"fo+ "x.""#, CaseMode::Sensitive, MatchMode::Regex)
+                .unwrap()
+                .to_string(),
+            "(and /fo+/ /x\\./)"
+        );
+        assert!(Query::parse("fo[", CaseMode::Sensitive, MatchMode::Regex).is_err());
+
+        for invalid in [
+            "(a",
+            "a )",
+            "a OR",
+            "OR a",
+            "()",
+            "\"a",
+            "cmd:",
+            "after:99999999d",
+            "/a(/",
+            "after:foo",
+        ] {
+            assert!(
+                Query::parse(invalid, CaseMode::Sensitive, MatchMode::Exact).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_args() {
+        let from_args = |args: &[&str], match_mode| {
+            let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            Query::from_args(&args, CaseMode::Sensitive, match_mode)
+                .unwrap()
+                .to_string()
+        };
+        let exact = |args: &[&str]| from_args(args, MatchMode::Exact);
+        assert_eq!(exact(&[]), "(and)");
+        assert_eq!(exact(&["", " "]), "(and)");
+        // Arguments without query syntax are matched literally, as a whole
+        assert_eq!(exact(&["git commit"]), "/git commit/");
+        assert_eq!(exact(&["-la"]), "/\\-la/");
+        assert_eq!(
+            exact(&["echo $(date)", "a-b"]),
+            "(and /echo \\$\\(date\\)/ /a\\-b/)"
+        );
+        assert_eq!(from_args(&["git co"], MatchMode::Fuzzy), "~git co");
+        // Other arguments are parsed as queries
+        assert_eq!(
+            exact(&["git", "(push OR pull)", "!force"]),
+            "(and /git/ (or /push/ /pull/) (not /force/))"
+        );
+        assert_eq!(exact(&["/fo+ o/"]), "/fo+ o/");
+        assert_eq!(exact(&["cmd:git", "x OR y"]), "(and cmd:git (or /x/ /y/))");
+        // `-word` is a word, only `!word` negates
+        assert_eq!(exact(&["\"a b\" -c !d"]), "(and /a b/ /\\-c/ (not /d/))");
+        assert_eq!(
+            exact(&["(push OR pull) -force"]),
+            "(and (or /push/ /pull/) /\\-force/)"
+        );
+
+        assert!(
+            Query::from_args(&["(a".to_owned()], CaseMode::Sensitive, MatchMode::Exact).is_err()
+        );
+
+        // A pasted command matches itself
+        let command = r#"git commit -m "wip""#;
+        let query =
+            Query::from_args(&[command.to_owned()], CaseMode::Sensitive, MatchMode::Exact).unwrap();
+        assert!(
+            query
+                .find(&HistEntry::new(crate::default_ts(), command), false)
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_input_from_args() {
+        let input = |args: &[&str]| {
+            Query::input_from_args(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+        };
+        assert_eq!(input(&["git", "", "commit"]), "git commit");
+        assert_eq!(input(&["git commit", "-la"]), r#""git commit" "-la""#);
+        assert_eq!(input(&[r#"a"b\c d"#]), r#""a\"b\\c d""#);
+        assert_eq!(
+            input(&["git (push OR pull) -f --rm !x"]),
+            r#"git (push OR pull) "-f" --rm !x"#
+        );
+        assert_eq!(input(&["(a"]), "(a");
+
+        // The input has the same meaning as the arguments
+        let args = [r#"git commit -m "wip""#, "(x OR -y)", "!z", "a b"];
+        let args = args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let from_args = Query::from_args(&args, CaseMode::Sensitive, MatchMode::Exact).unwrap();
+        let from_input = Query::parse(
+            &Query::input_from_args(&args),
+            CaseMode::Sensitive,
+            MatchMode::Exact,
+        )
+        .unwrap();
+        for command in [
+            r#"git commit -m "wip" -y a b"#,
+            r#"git commit -m "wip" x a b"#,
+            r#"git commit -m "wip" -y a b z"#,
+            r#"git commit -m "wip" -y b a"#,
+        ] {
+            let entry = HistEntry::new(crate::default_ts(), command);
+            assert_eq!(
+                from_input.find(&entry, false).is_some(),
+                from_args.find(&entry, false).is_some(),
+                "{}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn test_literal_input() {
+        let input = r#"grep -v "foo OR ( ) /tmp/ cmd:x !y a-b"#;
+        assert_eq!(
+            Query::literal_input(input),
+            r#"grep "-v" "\"foo" "OR" "(" ")" "/tmp/" "cmd:x" "!y" a-b"#
+        );
+        assert_eq!(
+            parse(&Query::literal_input(input)),
+            format!(
+                "(and {})",
+                input
+                    .split_whitespace()
+                    .map(|w| format!("/{}/", regex::escape(w)))
+                    .join(" ")
+            )
+        );
+        assert_eq!(Query::literal_input("  "), "");
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("", "anything"));
+        assert!(matches("git push", "git push origin"));
+        assert!(matches("push git", "git push origin"));
+        assert!(!matches("git pull", "git push origin"));
+        assert!(matches("git (push OR pull)", "git pull"));
+        assert!(matches("git -force", "git push"));
+        assert!(!matches("git -force", "git push --force"));
+        assert!(matches("GIT !(push OR pull)", "git log"));
+        assert!(!matches("\"git pull\"", "git  pull"));
+        assert!(matches("/git +pull/", "git  pull"));
+        assert!(matches("cmd:git", "cd ~/src && git pull"));
+        assert!(!matches("cmd:git", "cd ~/src/git"));
+        assert!(matches("arg:--force", "git push --force"));
+
+        let mut entry = HistEntry::new(
+            DateTime::parse_from_rfc3339("2026-09-15T12:00:00Z")
+                .unwrap()
+                .to_utc(),
+            "ls",
+        );
+        entry.source = Some(Rc::from("/home/me/work/.bash_history"));
+        let matches = |query| {
+            Query::parse(query, CaseMode::Insensitive, MatchMode::Exact)
+                .unwrap()
+                .find(&entry, false)
+                .is_some()
+        };
+        assert!(matches("after:2026-09-01 before:2026-10-01"));
+        assert!(!matches("after:2026-09-16"));
+        assert!(matches("file:WORK"));
+        assert!(!matches("file:home/you"));
+        assert!(matches("file:work OR file:private"));
+    }
+
+    #[test]
+    fn test_highlights_and_score() {
+        let entry = HistEntry::new(crate::default_ts(), "git push origin");
+        let query = Query::parse(
+            "git (push OR pull) -force",
+            CaseMode::Sensitive,
+            MatchMode::Exact,
+        )
+        .unwrap();
+        assert!(query.find(&entry, false).is_some());
+        // negated terms are not highlighted
+        let query2 = Query::parse("git -force", CaseMode::Sensitive, MatchMode::Exact).unwrap();
+        assert_eq!(query2.highlights(&entry), vec![0..3]);
+        // nor are alternatives that don't match as a whole
+        let query2 =
+            Query::parse("(git pull) OR push", CaseMode::Sensitive, MatchMode::Exact).unwrap();
+        assert_eq!(query2.highlights(&entry), vec![4..8]);
+        let query2 = Query::parse("git -origin", CaseMode::Sensitive, MatchMode::Exact).unwrap();
+        assert_eq!(query2.find(&entry, true), None);
+        assert_eq!(query.highlights(&entry), vec![0..3, 4..8]);
+        assert_eq!(query.find(&entry, false).unwrap().score, 0);
+
+        let query = Query::parse("gp -/x/", CaseMode::Sensitive, MatchMode::Fuzzy).unwrap();
+        assert!(query.find(&entry, false).is_some());
+        assert_eq!(query.highlights(&entry), vec![0..1, 4..5]);
+        assert_eq!(
+            query.find(&entry, true).unwrap(),
+            QueryMatch {
+                score: fuzzy_match("gp", &entry.command, CaseMode::Sensitive)
+                    .unwrap()
+                    .score,
+                highlights: vec![0..1, 4..5],
+            }
+        );
+        assert_eq!(
+            query.find(&entry, false).unwrap().score,
+            fuzzy_match("gp", &entry.command, CaseMode::Sensitive)
+                .unwrap()
+                .score
+        );
+    }
+}
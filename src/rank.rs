@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+
+use crate::default_ts;
+use crate::histfile::HistEntry;
+
+/// How matching entries are ordered. The best entries come last, i.e., closest
+/// to the prompt
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum SortOrder {
+    /// History order. In fuzzy mode, order by match quality instead
+    Time,
+    /// How often the command was run
+    Count,
+    /// A combination of how often and how recently the command was run
+    Frecency,
+}
+
+impl SortOrder {
+    /// The next sort order, for toggling through them in interactive mode
+    pub fn next(self) -> Self {
+        match self {
+            SortOrder::Time => SortOrder::Frecency,
+            SortOrder::Frecency => SortOrder::Count,
+            SortOrder::Count => SortOrder::Time,
+        }
+    }
+
+    /// The name shown in the interactive mode footer
+    pub fn name(self) -> &'static str {
+        match self {
+            SortOrder::Time => "time",
+            SortOrder::Count => "count",
+            SortOrder::Frecency => "frecency",
+        }
+    }
+}
+
+/// How often and when a command was last run, across all of its entries
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct CommandStats {
+    count: usize,
+    last_seen: DateTime<Utc>,
+}
+
+/// Ranks entries by the global occurrence counts of their commands
+#[derive(Clone, Debug)]
+pub struct Ranker {
+    stats: HashMap<String, CommandStats>,
+    now: DateTime<Utc>,
+}
+
+impl Ranker {
+    /// Collect the command statistics of `entries`. Frecency is computed
+    /// relative to `now`
+    pub fn new(entries: &[HistEntry], now: DateTime<Utc>) -> Self {
+        let mut stats: HashMap<String, CommandStats> = HashMap::new();
+        for e in entries {
+            stats
+                .entry(e.command.clone())
+                .and_modify(|s| {
+                    s.count += e.count;
+                    s.last_seen = s.last_seen.max(e.last_seen());
+                })
+                .or_insert(CommandStats {
+                    count: e.count,
+                    last_seen: e.last_seen(),
+                });
+        }
+        Self { stats, now }
+    }
+
+    /// The rank of `entry` for `order`. Higher is better. All entries have the
+    /// same rank for `SortOrder::Time`
+    pub fn rank(&self, order: SortOrder, entry: &HistEntry) -> u64 {
+        let Some(stats) = self.stats.get(&entry.command) else {
+            return 0;
+        };
+        match order {
+            SortOrder::Time => 0,
+            SortOrder::Count => stats.count as u64,
+            SortOrder::Frecency => stats.count as u64 * self.recency_weight(stats.last_seen),
+        }
+    }
+
+    /// Weight recently used commands higher, similar to zoxide
+    fn recency_weight(&self, last_seen: DateTime<Utc>) -> u64 {
+        if last_seen == default_ts() {
+            return 1;
+        }
+        let age = self.now - last_seen;
+        if age < Duration::hours(1) {
+            16
+        } else if age < Duration::days(1) {
+            8
+        } else if age < Duration::weeks(1) {
+            4
+        } else if age < Duration::days(30) {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Stable sort `items` by `order`, with the best ranked entry last. Items
+    /// with the same rank keep their order.
+    pub fn sort<T>(&self, order: SortOrder, items: &mut [T], entry: impl Fn(&T) -> &HistEntry) {
+        if order != SortOrder::Time {
+            items.sort_by_cached_key(|item| self.rank(order, entry(item)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools as _;
+
+    use super::*;
+
+    #[test]
+    fn test_rank() {
+        let now = default_ts() + Duration::days(1000);
+        let entries = vec![
+            HistEntry::new(now - Duration::days(100), "make"),
+            HistEntry::new(now - Duration::days(90), "make"),
+            HistEntry::new(now - Duration::days(80), "make"),
+            HistEntry::new(now - Duration::days(2), "ls"),
+            HistEntry::new(now - Duration::minutes(5), "kubectl get pods"),
+            HistEntry::new(default_ts(), "vim"),
+        ];
+        let ranker = Ranker::new(&entries, now);
+        let ranks = |order| entries.iter().map(|e| ranker.rank(order, e)).collect_vec();
+        assert_eq!(ranks(SortOrder::Time), vec![0; 6]);
+        assert_eq!(ranks(SortOrder::Count), vec![3, 3, 3, 1, 1, 1]);
+        assert_eq!(ranks(SortOrder::Frecency), vec![3, 3, 3, 4, 16, 1]);
+
+        // Counts of deduplicated entries are included
+        let mut entry = HistEntry::new(now, "vim");
+        entry.count = 4;
+        let ranker = Ranker::new(&[entry.clone(), HistEntry::new(default_ts(), "vim")], now);
+        assert_eq!(ranker.rank(SortOrder::Count, &entry), 5);
+        assert_eq!(ranker.rank(SortOrder::Frecency, &entry), 80);
+    }
+
+    #[test]
+    fn test_sort() {
+        let now = default_ts() + Duration::days(1000);
+        let entries = vec![
+            HistEntry::new(now - Duration::days(100), "make"),
+            HistEntry::new(now - Duration::days(2), "ls"),
+            HistEntry::new(now - Duration::days(90), "make"),
+            HistEntry::new(now - Duration::minutes(5), "kubectl get pods"),
+        ];
+        let ranker = Ranker::new(&entries, now);
+        let sorted = |order| {
+            let mut sorted = entries.iter().collect_vec();
+            ranker.sort(order, &mut sorted, |e| e);
+            sorted.iter().map(|e| e.command.as_str()).collect_vec()
+        };
+        assert_eq!(
+            sorted(SortOrder::Time),
+            vec!["make", "ls", "make", "kubectl get pods"]
+        );
+        assert_eq!(
+            sorted(SortOrder::Count),
+            vec!["ls", "kubectl get pods", "make", "make"]
+        );
+        assert_eq!(
+            sorted(SortOrder::Frecency),
+            vec!["make", "make", "ls", "kubectl get pods"]
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+/// Reserved words that can precede the command word of a simple command
+const RESERVED_PREFIXES: &[&str] = &[
+    "!", "{", "}", "if", "then", "else", "elif", "fi", "while", "until", "do", "done", "time",
+];
+
+/// A simple command: a list of words, e.g., `git commit -m msg` in
+/// `make && git commit -m msg`. Quotes and escapes are removed from the words,
+/// redirections are dropped.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct SimpleCommand {
+    pub words: Vec<String>,
+}
+
+impl SimpleCommand {
+    /// The index of the command word, i.e., the first word that is not a variable
+    /// assignment or a reserved word like `if` or `time`
+    fn program_idx(&self) -> Option<usize> {
+        self.words
+            .iter()
+            .position(|w| !is_assignment(w) && !RESERVED_PREFIXES.contains(&w.as_str()))
+    }
+
+    /// The command word (argv0) without directories, e.g., `git` for
+    /// `GIT_PAGER= /usr/bin/git log`
+    pub fn program(&self) -> Option<&str> {
+        let word = &self.words[self.program_idx()?];
+        Path::new(word).file_name().and_then(|name| name.to_str())
+    }
+
+    /// The words after the command word
+    pub fn args(&self) -> &[String] {
+        match self.program_idx() {
+            Some(idx) => &self.words[idx + 1..],
+            None => &[],
+        }
+    }
+}
+
+/// `NAME=value`
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+/// Split a command line into simple commands, similar to how bash parses it.
+///
+/// Commands are separated by newlines, `;`, `&`, `|`, `&&`, `||`, and parentheses
+/// (subshells). Single quotes, double quotes, and backslash escapes are handled
+/// like bash does. The commands inside `$(...)` and backticks are returned as
+/// separate simple commands, after the one they appear in. Comments and
+/// redirections (e.g., `> file`, `2>&1`) are dropped. This is a best-effort
+/// lexer: it does not fail on incomplete input such as unclosed quotes.
+pub fn split_commands(command: &str) -> Vec<SimpleCommand> {
+    let mut lexer = Lexer {
+        chars: command.chars().peekable(),
+        commands: Vec::new(),
+        nested: Vec::new(),
+        current: SimpleCommand::default(),
+        word: None,
+        skip_word: false,
+    };
+    lexer.run();
+    lexer.commands
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    commands: Vec<SimpleCommand>,
+    /// Commands from command substitutions in the current simple command
+    nested: Vec<SimpleCommand>,
+    current: SimpleCommand,
+    /// The word being read, if any
+    word: Option<String>,
+    /// The current word is the target of a redirection and is dropped
+    skip_word: bool,
+}
+
+impl Lexer<'_> {
+    fn run(&mut self) {
+        while let Some(c) = self.chars.next() {
+            match c {
+                '\\' => match self.chars.next() {
+                    // line continuation
+                    Some('\n') => (),
+                    Some(c) => self.push(c),
+                    None => self.push('\\'),
+                },
+                '\'' => {
+                    self.word.get_or_insert_default();
+                    while let Some(c) = self.chars.next_if(|&c| c != '\'') {
+                        self.push(c);
+                    }
+                    // the closing quote
+                    self.chars.next();
+                }
+                '"' => self.double_quoted(),
+                '$' if self.chars.peek() == Some(&'(') => {
+                    self.chars.next();
+                    let inner = self.take_until_paren();
+                    self.push_str(&format!("$({})", inner));
+                    self.nested.extend(split_commands(&inner));
+                }
+                '`' => {
+                    let inner = self.take_until_backtick();
+                    self.push_str(&format!("`{}`", inner));
+                    self.nested.extend(split_commands(&inner));
+                }
+                '#' if self.word.is_none() => {
+                    // a comment, up to the end of the line
+                    while self.chars.next_if(|&c| c != '\n').is_some() {}
+                }
+                '<' | '>' => self.redirection(c),
+                '&' if self.chars.peek() == Some(&'>') => self.redirection(c),
+                '\n' | ';' | '&' | '|' | '(' | ')' => {
+                    self.end_word();
+                    self.end_command();
+                }
+                c if c.is_whitespace() => self.end_word(),
+                c => self.push(c),
+            }
+        }
+        self.end_word();
+        self.end_command();
+    }
+
+    fn push(&mut self, c: char) {
+        self.word.get_or_insert_default().push(c);
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.word.get_or_insert_default().push_str(s);
+    }
+
+    /// Read a double-quoted string. In double quotes, backslashes only escape
+    /// `$`, `` ` ``, `"`, `\`, and newlines
+    fn double_quoted(&mut self) {
+        self.word.get_or_insert_default();
+        while let Some(c) = self.chars.next() {
+            match c {
+                '"' => break,
+                '\\' => match self
+                    .chars
+                    .next_if(|c| matches!(c, '$' | '`' | '"' | '\\' | '\n'))
+                {
+                    Some('\n') => (),
+                    Some(c) => self.push(c),
+                    None => self.push('\\'),
+                },
+                '$' if self.chars.peek() == Some(&'(') => {
+                    self.chars.next();
+                    let inner = self.take_until_paren();
+                    self.push_str(&format!("$({})", inner));
+                    self.nested.extend(split_commands(&inner));
+                }
+                '`' => {
+                    let inner = self.take_until_backtick();
+                    self.push_str(&format!("`{}`", inner));
+                    self.nested.extend(split_commands(&inner));
+                }
+                c => self.push(c),
+            }
+        }
+    }
+
+    /// Read up to the `)` that closes a `$(`, and return the text in between
+    fn take_until_paren(&mut self) -> String {
+        let mut inner = String::new();
+        let mut depth = 0;
+        let mut quote = None;
+        while let Some(c) = self.chars.next() {
+            match (c, quote) {
+                ('\\', _) if quote != Some('\'') => {
+                    inner.push(c);
+                    if let Some(c) = self.chars.next() {
+                        inner.push(c);
+                    }
+                    continue;
+                }
+                ('\'' | '"', None) => quote = Some(c),
+                (c, Some(q)) if c == q => quote = None,
+                ('(', None) => depth += 1,
+                (')', None) if depth == 0 => break,
+                (')', None) => depth -= 1,
+                _ => (),
+            }
+            inner.push(c);
+        }
+        inner
+    }
+
+    /// Read up to the closing backtick, and return the text in between
+    fn take_until_backtick(&mut self) -> String {
+        let mut inner = String::new();
+        while let Some(c) = self.chars.next() {
+            match c {
+                '`' => break,
+                '\\' => match self.chars.next_if(|c| matches!(c, '$' | '`' | '\\')) {
+                    Some(c) => inner.push(c),
+                    None => inner.push('\\'),
+                },
+                c => inner.push(c),
+            }
+        }
+        inner
+    }
+
+    /// Handle a redirection operator starting with `c`. The word that follows the
+    /// operator is the target of the redirection and is dropped.
+    fn redirection(&mut self, c: char) {
+        // A file descriptor number before the operator (`2>`) is part of it
+        if self
+            .word
+            .as_ref()
+            .is_some_and(|w| !w.is_empty() && w.chars().all(|c| c.is_ascii_digit()))
+        {
+            self.word = None;
+        }
+        self.end_word();
+        if c == '&' {
+            // `&>` or `&>>`
+            self.chars.next();
+        }
+        while self
+            .chars
+            .next_if(|&c| matches!(c, '<' | '>' | '&' | '|'))
+            .is_some()
+        {}
+        self.skip_word = true;
+    }
+
+    fn end_word(&mut self) {
+        if let Some(word) = self.word.take() {
+            if self.skip_word {
+                self.skip_word = false;
+            } else {
+                self.current.words.push(word);
+            }
+        }
+    }
+
+    fn end_command(&mut self) {
+        self.skip_word = false;
+        let current = std::mem::take(&mut self.current);
+        if !current.words.is_empty() {
+            self.commands.push(current);
+        }
+        self.commands.append(&mut self.nested);
+    }
+}
+
+/// Filters commands by their command words and arguments (`--program` and `--arg`)
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct CommandFilter {
+    /// If not empty, one of these must be the command word of a simple command
+    pub programs: Vec<String>,
+    /// All of these must be arguments of that simple command
+    pub args: Vec<String>,
+}
+
+impl CommandFilter {
+    pub fn is_empty(&self) -> bool {
+        self.programs.is_empty() && self.args.is_empty()
+    }
+
+    /// Check if one of the simple commands of `command` runs one of `programs`
+    /// with all of `args`
+    pub fn matches(&self, command: &str) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        split_commands(command).iter().any(|c| {
+            (self.programs.is_empty()
+                || c.program()
+                    .is_some_and(|program| self.programs.iter().any(|p| p == program)))
+                && self.args.iter().all(|arg| c.args().contains(arg))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools as _;
+
+    use super::*;
+
+    fn words(command: &str) -> Vec<Vec<String>> {
+        split_commands(command)
+            .into_iter()
+            .map(|c| c.words)
+            .collect_vec()
+    }
+
+    #[test]
+    fn test_split_commands() {
+        assert_eq!(words("git status"), vec![vec!["git", "status"]]);
+        assert_eq!(words("  "), Vec::<Vec<String>>::new());
+        assert_eq!(
+            words("make && ./run | grep foo; ls -la & (cd x || exit)\necho"),
+            vec![
+                vec!["make"],
+                vec!["./run"],
+                vec!["grep", "foo"],
+                vec!["ls", "-la"],
+                vec!["cd", "x"],
+                vec!["exit"],
+                vec!["echo"],
+            ]
+        );
+        // quotes and escapes
+        assert_eq!(
+            words(r#"echo 'a b;c' "d \"e\" $x\n" f\ g\;h"#),
+            vec![vec!["echo", "a b;c", r#"d "e" $x\n"#, "f g;h"]]
+        );
+        assert_eq!(words("echo '' \"\""), vec![vec!["echo", "", ""]]);
+        assert_eq!(words("echo a\\\nb"), vec![vec!["echo", "ab"]]);
+        // command substitution
+        assert_eq!(
+            words("echo \"$(git rev-parse 'HEAD)')\" `date`"),
+            vec![
+                vec!["echo", "$(git rev-parse 'HEAD)')", "`date`"],
+                vec!["git", "rev-parse", "HEAD)"],
+                vec!["date"],
+            ]
+        );
+        assert_eq!(
+            words("x=$(cat $(ls)) y"),
+            vec![
+                vec!["x=$(cat $(ls))", "y"],
+                vec!["cat", "$(ls)"],
+                vec!["ls"]
+            ]
+        );
+        // redirections
+        assert_eq!(
+            words("make >build.log 2>&1 < /dev/null; cat &>> out foo"),
+            vec![vec!["make"], vec!["cat", "foo"]]
+        );
+        // comments
+        assert_eq!(
+            words("ls # list files\nfoo#bar"),
+            vec![vec!["ls"], vec!["foo#bar"]]
+        );
+        // incomplete input
+        assert_eq!(words("echo 'foo"), vec![vec!["echo", "foo"]]);
+        assert_eq!(words("echo $(ls"), vec![vec!["echo", "$(ls)"], vec!["ls"]]);
+    }
+
+    #[test]
+    fn test_program() {
+        let program = |command| {
+            split_commands(command)
+                .iter()
+                .map(|c| c.program().map(str::to_owned))
+                .collect_vec()
+        };
+        assert_eq!(program("git status"), vec![Some("git".to_owned())]);
+        assert_eq!(
+            program("GIT_PAGER= /usr/bin/git log"),
+            vec![Some("git".to_owned())]
+        );
+        assert_eq!(
+            program("if grep -q x y; then time make; fi"),
+            vec![Some("grep".to_owned()), Some("make".to_owned()), None]
+        );
+        assert_eq!(program("FOO=bar"), vec![None]);
+
+        let cmd = &split_commands("A=1 git commit -m 'a msg'")[0];
+        assert_eq!(cmd.args(), ["commit", "-m", "a msg"]);
+        assert!(split_commands("A=1")[0].args().is_empty());
+    }
+
+    #[test]
+    fn test_command_filter() {
+        let filter = |programs: &[&str], args: &[&str]| CommandFilter {
+            programs: programs.iter().map(|s| s.to_string()).collect(),
+            args: args.iter().map(|s| s.to_string()).collect(),
+        };
+        assert!(filter(&[], &[]).matches("anything"));
+
+        let git = filter(&["git"], &[]);
+        assert!(git.matches("git status"));
+        assert!(git.matches("cd foo && git pull"));
+        assert!(git.matches("echo $(git rev-parse HEAD)"));
+        assert!(!git.matches("cd ~/src/git"));
+        assert!(!git.matches("echo 'git status'"));
+        assert!(!git.matches("gitk"));
+        assert!(filter(&["hg", "git"], &[]).matches("git log"));
+
+        let git_push = filter(&["git"], &["push"]);
+        assert!(git_push.matches("git push origin"));
+        assert!(!git_push.matches("git pull"));
+        // the argument must belong to the same simple command
+        assert!(!git_push.matches("git pull && echo push"));
+        assert!(filter(&[], &["--force"]).matches("git push --force"));
+        assert!(!filter(&[], &["--force"]).matches("git push --force-with-lease"));
+    }
+}
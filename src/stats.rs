@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use chrono::Datelike as _;
+use chrono::Duration;
+use chrono::NaiveDate;
+use chrono::TimeZone;
+use chrono::Timelike as _;
+use chrono::Weekday;
+use itertools::Itertools as _;
+use serde_json::json;
+
+use crate::default_ts;
+use crate::histfile::HistEntry;
+use crate::shell::split_commands;
+
+/// The width of the bars in the activity histograms
+const BAR_WIDTH: usize = 40;
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// Arguments of the `stats` subcommand
+#[derive(clap::Args)]
+pub struct StatsArgs {
+    /// How many commands and streaks to list
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub top: usize,
+
+    /// Print the statistics as a JSON object instead of tables
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// A run of consecutive days on which at least one command was run
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Streak {
+    pub first_day: NaiveDate,
+    pub last_day: NaiveDate,
+}
+
+impl Streak {
+    /// The length of the streak in days
+    pub fn days(&self) -> i64 {
+        (self.last_day - self.first_day).num_days() + 1
+    }
+}
+
+/// Statistics about a history
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Stats {
+    /// The number of commands run
+    pub total: usize,
+    /// The number of distinct commands
+    pub unique: usize,
+    /// The most used programs (the command words of the simple commands, see
+    /// `split_commands`) with their counts
+    pub top_programs: Vec<(String, usize)>,
+    /// The most used commands with their counts
+    pub top_commands: Vec<(String, usize)>,
+    /// The number of commands run in each hour of the day
+    pub by_hour: [usize; 24],
+    /// The number of commands run on each day of the week, starting on Monday
+    pub by_weekday: [usize; 7],
+    /// The longest streaks, longest first
+    pub streaks: Vec<Streak>,
+}
+
+impl Stats {
+    /// Compute the statistics of `entries`, keeping the `top` commands and
+    /// streaks. Hours, weekdays, and days are in the time zone `tz`. Entries
+    /// without timestamps only count towards the totals and top commands.
+    pub fn compute<Tz: TimeZone>(entries: &[HistEntry], top: usize, tz: &Tz) -> Self {
+        let mut commands: HashMap<&str, usize> = HashMap::new();
+        let mut programs: HashMap<String, usize> = HashMap::new();
+        let mut by_hour = [0; 24];
+        let mut by_weekday = [0; 7];
+        let mut days = Vec::new();
+        for e in entries {
+            *commands.entry(&e.command).or_default() += e.count;
+            // Each program counts once per entry, e.g., `git add x && git commit` is
+            // one use of git
+            let split = split_commands(&e.command);
+            for program in split.iter().filter_map(|c| c.program()).unique() {
+                *programs.entry(program.to_owned()).or_default() += e.count;
+            }
+            if e.ts != default_ts() {
+                let ts = e.ts.with_timezone(tz);
+                by_hour[ts.hour() as usize] += e.count;
+                by_weekday[ts.weekday().num_days_from_monday() as usize] += e.count;
+                days.push(ts.date_naive());
+            }
+        }
+        Self {
+            total: entries.iter().map(|e| e.count).sum(),
+            unique: commands.len(),
+            top_programs: top_n(programs, top),
+            top_commands: top_n(commands, top),
+            by_hour,
+            by_weekday,
+            streaks: longest_streaks(days, top),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let counts = |key: &str, top: &[(String, usize)]| {
+            top.iter()
+                .map(|(name, count)| json!({key: name, "count": count}))
+                .collect_vec()
+        };
+        json!({
+            "total": self.total,
+            "unique": self.unique,
+            "top_programs": counts("program", &self.top_programs),
+            "top_commands": counts("command", &self.top_commands),
+            "by_hour": self.by_hour,
+            "by_weekday": WEEKDAYS
+                .iter()
+                .zip(self.by_weekday)
+                .map(|(day, count)| (day.to_string(), json!(count)))
+                .collect::<serde_json::Map<_, _>>(),
+            "streaks": self
+                .streaks
+                .iter()
+                .map(|s| json!({
+                    "first_day": s.first_day.to_string(),
+                    "last_day": s.last_day.to_string(),
+                    "days": s.days(),
+                }))
+                .collect_vec(),
+        })
+    }
+
+    /// Format the statistics as human readable tables
+    pub fn to_table(&self) -> String {
+        let mut ret = String::new();
+        // unwrap()s are safe: writing to a String cannot fail
+        writeln!(ret, "Total commands:   {}", self.total).unwrap();
+        writeln!(ret, "Unique commands:  {}", self.unique).unwrap();
+
+        for (title, top) in [
+            ("Top programs", &self.top_programs),
+            ("Top commands", &self.top_commands),
+        ] {
+            writeln!(ret, "\n{}", title).unwrap();
+            for (command, count) in top {
+                writeln!(ret, "  {:>7}  {}", count, command.replace('\n', " ↵ ")).unwrap();
+            }
+        }
+
+        let max = self.by_hour.iter().max().copied().unwrap_or(0);
+        writeln!(ret, "\nActivity by hour of day").unwrap();
+        for (hour, count) in self.by_hour.iter().enumerate() {
+            writeln!(ret, "  {:02}  {:>7}  {}", hour, count, bar(*count, max)).unwrap();
+        }
+        let max = self.by_weekday.iter().max().copied().unwrap_or(0);
+        writeln!(ret, "\nActivity by day of week").unwrap();
+        for (day, count) in WEEKDAYS.iter().zip(self.by_weekday) {
+            writeln!(ret, "  {}  {:>7}  {}", day, count, bar(count, max)).unwrap();
+        }
+
+        writeln!(ret, "\nLongest streaks").unwrap();
+        for streak in &self.streaks {
+            writeln!(
+                ret,
+                "  {:>4} days  {} to {}",
+                streak.days(),
+                streak.first_day,
+                streak.last_day
+            )
+            .unwrap();
+        }
+        ret
+    }
+}
+
+/// The `n` entries with the highest counts, highest first. Ties are ordered by
+/// name
+fn top_n<S: AsRef<str>>(counts: HashMap<S, usize>, n: usize) -> Vec<(String, usize)> {
+    counts
+        .into_iter()
+        .sorted_by(|a, b| b.1.cmp(&a.1).then(a.0.as_ref().cmp(b.0.as_ref())))
+        .take(n)
+        .map(|(s, count)| (s.as_ref().to_owned(), count))
+        .collect()
+}
+
+/// The `n` longest runs of consecutive days in `days`, longest first. Streaks
+/// of the same length are ordered by date, most recent first
+fn longest_streaks(mut days: Vec<NaiveDate>, n: usize) -> Vec<Streak> {
+    days.sort_unstable();
+    days.dedup();
+    let mut streaks: Vec<Streak> = Vec::new();
+    for day in days {
+        match streaks.last_mut() {
+            Some(streak) if streak.last_day + Duration::days(1) == day => streak.last_day = day,
+            _ => streaks.push(Streak {
+                first_day: day,
+                last_day: day,
+            }),
+        }
+    }
+    streaks.sort_by(|a, b| b.days().cmp(&a.days()).then(b.first_day.cmp(&a.first_day)));
+    streaks.truncate(n);
+    streaks
+}
+
+/// A bar of up to `BAR_WIDTH` characters, proportional to `count / max`
+fn bar(count: usize, max: usize) -> String {
+    if max == 0 {
+        return String::new();
+    }
+    "█".repeat((count * BAR_WIDTH).div_ceil(max))
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::DateTime;
+    use chrono::Utc;
+
+    use super::*;
+
+    fn mkentry(ts: &str, cmd: &str) -> HistEntry {
+        HistEntry::new(DateTime::parse_from_rfc3339(ts).unwrap().to_utc(), cmd)
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_stats() {
+        let entries = vec![
+            // a Monday
+            mkentry("2026-10-05T09:00:00Z", "git status"),
+            mkentry("2026-10-05T09:30:00Z", "git commit"),
+            mkentry("2026-10-06T10:00:00Z", "git status"),
+            mkentry("2026-10-07T23:00:00Z", "ls -la"),
+            mkentry("2026-10-10T09:00:00Z", "git status"),
+            mkentry("2026-10-11T09:00:00Z", "cd src && ls"),
+            HistEntry::new(default_ts(), "make"),
+        ];
+        let stats = Stats::compute(&entries, 2, &Utc);
+        assert_eq!(stats.total, 7);
+        assert_eq!(stats.unique, 5);
+        // `cd src && ls` counts for both `cd` and `ls`
+        assert_eq!(
+            stats.top_programs,
+            vec![("git".to_owned(), 4), ("ls".to_owned(), 2)]
+        );
+        assert_eq!(
+            stats.top_commands,
+            vec![("git status".to_owned(), 3), ("cd src && ls".to_owned(), 1)]
+        );
+        assert_eq!(stats.by_hour[9], 4);
+        assert_eq!(stats.by_hour[10], 1);
+        assert_eq!(stats.by_hour[23], 1);
+        assert_eq!(stats.by_hour.iter().sum::<usize>(), 6);
+        assert_eq!(stats.by_weekday, [2, 1, 1, 0, 0, 1, 1]);
+        assert_eq!(
+            stats.streaks,
+            vec![
+                Streak {
+                    first_day: date("2026-10-05"),
+                    last_day: date("2026-10-07"),
+                },
+                Streak {
+                    first_day: date("2026-10-10"),
+                    last_day: date("2026-10-11"),
+                },
+            ]
+        );
+
+        let json = stats.to_json();
+        assert_eq!(
+            json["top_programs"][0],
+            json!({"program": "git", "count": 4})
+        );
+        assert_eq!(
+            json["top_commands"][0],
+            json!({"command": "git status", "count": 3})
+        );
+        assert_eq!(json["by_weekday"]["Mon"], 2);
+        assert_eq!(
+            json["streaks"][0],
+            json!({"first_day": "2026-10-05", "last_day": "2026-10-07", "days": 3})
+        );
+
+        let table = stats.to_table();
+        assert!(table.starts_with("Total commands:   7\nUnique commands:  5\n"));
+        assert!(table.contains("\n        3  git status\n"));
+        assert!(table.contains("\n     3 days  2026-10-05 to 2026-10-07\n"));
+    }
+
+    #[test]
+    fn test_stats_empty() {
+        let stats = Stats::compute(&[], 10, &Utc);
+        assert_eq!(stats.total, 0);
+        assert!(stats.top_commands.is_empty());
+        assert!(stats.streaks.is_empty());
+        assert!(
+            stats
+                .to_table()
+                .contains("Activity by hour of day\n  00        0  \n")
+        );
+    }
+
+    #[test]
+    fn test_bar() {
+        assert_eq!(bar(0, 0), "");
+        assert_eq!(bar(0, 10), "");
+        assert_eq!(bar(10, 10).chars().count(), BAR_WIDTH);
+        assert_eq!(bar(1, 10).chars().count(), BAR_WIDTH / 10);
+    }
+}
@@ -0,0 +1,281 @@
+use anyhow::anyhow;
+use anyhow::bail;
+use chrono::DateTime;
+use chrono::Local;
+use chrono::format::Item;
+use chrono::format::StrftimeItems;
+
+use crate::histfile::HistEntry;
+
+/// The timestamp format used by `{ts}` without an explicit format
+const DEFAULT_TS_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// An output template for `--template`, e.g., `{id}\t{ts:%H:%M}\t{cmd:oneline}`
+///
+/// Placeholders are enclosed in braces and can have a format spec after a colon:
+/// * `{id}`: the entry ID
+/// * `{ts}`, `{ts:<strftime>}`: the timestamp in local time, e.g., `{ts:%Y-%m-%d}`
+/// * `{cmd}`: the command as-is
+/// * `{cmd:quoted}`: the command quoted for the shell (in single quotes)
+/// * `{cmd:escaped}`: the command with backslashes, newlines, and tabs escaped
+/// * `{cmd:oneline}`: the lines of a multi-line command joined with ` ↵ `
+/// * `{source}`: the history file the entry came from
+/// * `{duration}`: how long the command ran, in seconds, if known
+/// * `{paths}`: the paths the command used, separated by spaces, if known
+/// * `{count}`: how many times the command was run (see `--dedup`)
+/// * `{first_seen}`, `{last_seen}`, also with `:<strftime>`: when the command was
+///   first and last run. They differ from `{ts}` if duplicates were removed
+///
+/// `{{` and `}}` are literal braces. `\t`, `\n`, `\0` and `\\` are replaced by a
+/// tab, newline, NUL, and backslash respectively.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Template {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum TemplatePart {
+    Literal(String),
+    Id,
+    Ts(String),
+    FirstSeen(String),
+    LastSeen(String),
+    Cmd(CmdStyle),
+    Source,
+    Duration,
+    Paths,
+    Count,
+}
+
+/// How `{cmd}` is rendered
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum CmdStyle {
+    Raw,
+    Quoted,
+    Escaped,
+    OneLine,
+}
+
+impl Template {
+    /// Parse a template. See [`Template`] for the syntax
+    pub fn parse(template: &str) -> anyhow::Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some('0') => literal.push('\0'),
+                    Some('\\') => literal.push('\\'),
+                    Some(other) => {
+                        literal.push('\\');
+                        literal.push(other);
+                    }
+                    None => literal.push('\\'),
+                },
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let (placeholder, rest) = chars
+                        .as_str()
+                        .split_once('}')
+                        .ok_or_else(|| anyhow!("Unclosed `{{` in template `{}`", template))?;
+                    chars = rest.chars();
+                    if !literal.is_empty() {
+                        parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(TemplatePart::parse(placeholder)?);
+                }
+                '}' => bail!("Unmatched `}}` in template `{}`. Use `}}}}`", template),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+        Ok(Self { parts })
+    }
+
+    /// Render `entry` with this template
+    pub fn render(&self, id: &str, entry: &HistEntry) -> String {
+        let format_ts = |ts, fmt| DateTime::<Local>::from(ts).format(fmt).to_string();
+        let mut ret = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(s) => ret += s,
+                TemplatePart::Id => ret += id,
+                TemplatePart::Ts(fmt) => ret += &format_ts(entry.ts, fmt),
+                TemplatePart::FirstSeen(fmt) => ret += &format_ts(entry.first_seen(), fmt),
+                TemplatePart::LastSeen(fmt) => ret += &format_ts(entry.last_seen(), fmt),
+                TemplatePart::Cmd(CmdStyle::Raw) => ret += &entry.command,
+                TemplatePart::Cmd(CmdStyle::Quoted) => ret += &shell_quote(&entry.command),
+                TemplatePart::Cmd(CmdStyle::Escaped) => ret += &escape(&entry.command),
+                TemplatePart::Cmd(CmdStyle::OneLine) => {
+                    ret += &entry.command.lines().collect::<Vec<_>>().join(" ↵ ")
+                }
+                TemplatePart::Source => ret += entry.source.as_deref().unwrap_or_default(),
+                TemplatePart::Duration => {
+                    if let Some(duration) = entry.duration {
+                        ret += &duration.num_seconds().to_string();
+                    }
+                }
+                TemplatePart::Paths => ret += &entry.paths.join(" "),
+                TemplatePart::Count => ret += &entry.count.to_string(),
+            }
+        }
+        ret
+    }
+}
+
+impl TemplatePart {
+    /// Parse the contents of a `{...}` placeholder
+    fn parse(placeholder: &str) -> anyhow::Result<Self> {
+        let (name, spec) = match placeholder.split_once(':') {
+            Some((name, spec)) => (name, Some(spec)),
+            None => (placeholder, None),
+        };
+        let ts_format = || -> anyhow::Result<String> {
+            let fmt = spec.unwrap_or(DEFAULT_TS_FORMAT);
+            if StrftimeItems::new(fmt).any(|item| item == Item::Error) {
+                bail!("Invalid timestamp format `{}`", fmt);
+            }
+            Ok(fmt.to_owned())
+        };
+        let part = match (name, spec) {
+            ("id", None) => TemplatePart::Id,
+            ("ts", _) => TemplatePart::Ts(ts_format()?),
+            ("first_seen", _) => TemplatePart::FirstSeen(ts_format()?),
+            ("last_seen", _) => TemplatePart::LastSeen(ts_format()?),
+            ("cmd", None) => TemplatePart::Cmd(CmdStyle::Raw),
+            ("cmd", Some("quoted")) => TemplatePart::Cmd(CmdStyle::Quoted),
+            ("cmd", Some("escaped")) => TemplatePart::Cmd(CmdStyle::Escaped),
+            ("cmd", Some("oneline")) => TemplatePart::Cmd(CmdStyle::OneLine),
+            ("cmd", Some(spec)) => bail!(
+                "Unknown format `{}` for `{{cmd}}`. Use `quoted`, `escaped`, or `oneline`",
+                spec
+            ),
+            ("source", None) => TemplatePart::Source,
+            ("duration", None) => TemplatePart::Duration,
+            ("paths", None) => TemplatePart::Paths,
+            ("count", None) => TemplatePart::Count,
+            (_, Some(_)) if ["id", "source", "duration", "paths", "count"].contains(&name) => {
+                bail!("`{{{}}}` does not take a format", name)
+            }
+            _ => bail!(
+                "Unknown placeholder `{{{}}}`. Use `id`, `ts`, `cmd`, `source`, `duration`, \
+                 `paths`, `count`, `first_seen`, or `last_seen`",
+                placeholder
+            ),
+        };
+        Ok(part)
+    }
+}
+
+/// Quote `s` for the shell, using single quotes
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Escape backslashes, newlines, and tabs
+fn escape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => ret += r"\\",
+            '\n' => ret += r"\n",
+            '\t' => ret += r"\t",
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use chrono::Duration;
+
+    use super::*;
+    use crate::histfile::DedupMode;
+    use crate::histfile::dedup_entries;
+
+    fn render(template: &str, entry: &HistEntry) -> String {
+        Template::parse(template).unwrap().render("2a", entry)
+    }
+
+    #[test]
+    fn test_template_parse() {
+        assert_eq!(
+            Template::parse(r"{id}\t{{x}}").unwrap().parts,
+            vec![TemplatePart::Id, TemplatePart::Literal("\t{x}".to_owned())]
+        );
+        assert_eq!(
+            Template::parse("{ts:%H:%M} {cmd:quoted}").unwrap().parts,
+            vec![
+                TemplatePart::Ts("%H:%M".to_owned()),
+                TemplatePart::Literal(" ".to_owned()),
+                TemplatePart::Cmd(CmdStyle::Quoted),
+            ]
+        );
+        assert!(Template::parse("{id").is_err());
+        assert!(Template::parse("id}").is_err());
+        assert!(Template::parse("{foo}").is_err());
+        assert!(Template::parse("{id:x}").is_err());
+        assert!(Template::parse("{cmd:foo}").is_err());
+        assert!(Template::parse("{ts:%Q}").is_err());
+    }
+
+    #[test]
+    fn test_template_render() {
+        let mut entry = HistEntry::new(
+            DateTime::from_timestamp(1700000000, 0).unwrap(),
+            "echo 'a'\n\tb\\",
+        );
+        let ts = DateTime::<Local>::from(entry.ts);
+        assert_eq!(
+            render("{id} {ts}", &entry),
+            format!("2a {}", ts.format("%Y-%m-%d %H:%M:%S"))
+        );
+        assert_eq!(
+            render(r"{ts:%H:%M}\0", &entry),
+            format!("{}\0", ts.format("%H:%M"))
+        );
+        assert_eq!(render("{cmd}", &entry), "echo 'a'\n\tb\\");
+        assert_eq!(render("{cmd:quoted}", &entry), "'echo '\\''a'\\''\n\tb\\'");
+        assert_eq!(render("{cmd:escaped}", &entry), r"echo 'a'\n\tb\\");
+        assert_eq!(render("{cmd:oneline}", &entry), "echo 'a' ↵ \tb\\");
+        assert_eq!(render("[{source}|{duration}|{paths}]", &entry), "[||]");
+        assert_eq!(render("{count}", &entry), "1");
+        assert_eq!(
+            render("{first_seen:%H:%M} {last_seen}", &entry),
+            format!("{} {}", ts.format("%H:%M"), ts.format("%Y-%m-%d %H:%M:%S"))
+        );
+
+        entry.source = Some(Rc::from("/tmp/hist"));
+        entry.duration = Some(Duration::seconds(12));
+        entry.paths = vec!["a".to_owned(), "b".to_owned()];
+        assert_eq!(
+            render("[{source}|{duration}|{paths}]", &entry),
+            "[/tmp/hist|12|a b]"
+        );
+
+        // A deduplicated entry
+        let mut dup = entry.clone();
+        dup.ts += Duration::days(1);
+        let entry = dedup_entries(vec![entry, dup], DedupMode::GlobalFirst).remove(0);
+        let last = DateTime::<Local>::from(entry.ts + Duration::days(1));
+        assert_eq!(
+            render("{count} {first_seen:%F} {last_seen:%F}", &entry),
+            format!("2 {} {}", ts.format("%F"), last.format("%F"))
+        );
+    }
+}
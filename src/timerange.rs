@@ -0,0 +1,204 @@
+use std::sync::LazyLock;
+
+use anyhow::anyhow;
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Local;
+use chrono::Months;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::NaiveTime;
+use chrono::TimeZone as _;
+use chrono::Utc;
+use regex::Regex;
+
+use crate::default_ts;
+use crate::histfile::HistEntry;
+
+/// `<N><unit>` or `<N> <unit> ago`, e.g., `3d`, `2 weeks ago`
+static RELATIVE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\d+)\s*([a-z]+)(\s+ago)?$").unwrap());
+
+/// Restricts history entries to a range of time. Both ends are inclusive.
+///
+/// Entries without a timestamp (i.e., that have `default_ts()`) are never
+/// in a range that has at least one bound, since we cannot tell when they
+/// were run.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct TimeRange {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl TimeRange {
+    /// Check if the entry's timestamp is within this range.
+    pub fn contains(&self, entry: &HistEntry) -> bool {
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        entry.ts != default_ts()
+            && self.since.is_none_or(|since| entry.ts >= since)
+            && self.until.is_none_or(|until| entry.ts <= until)
+    }
+}
+
+/// clap value parser for `--since` and `--until`
+pub fn parse_time_arg(spec: &str) -> anyhow::Result<DateTime<Utc>> {
+    parse_time_spec(spec, Local::now())
+}
+
+/// Parse a point in time, relative to `now`. Supported are:
+/// * `now`, `today` (midnight), `yesterday` (midnight)
+/// * Absolute dates and times in local time: `2026-10-01`, `2026-10-01T14:00`,
+///   `2026-10-01 14:00:30`. A date without a time means midnight.
+/// * RFC 3339 timestamps: `2026-10-01T14:00:00+02:00`
+/// * Relative times: `<N><unit>` or `<N> <unit> ago`, e.g., `3d` or `2 weeks ago`.
+///   Units are seconds (`s`), minutes (`m`, `min`), hours (`h`), days (`d`),
+///   weeks (`w`), months (`mo`), and years (`y`).
+pub fn parse_time_spec(spec: &str, now: DateTime<Local>) -> anyhow::Result<DateTime<Utc>> {
+    let spec = spec.trim().to_lowercase();
+    let midnight = |date: NaiveDate| {
+        Local
+            .from_local_datetime(&date.and_time(NaiveTime::MIN))
+            .earliest()
+            .ok_or_else(|| anyhow!("Invalid local time for `{}`", date))
+    };
+    match spec.as_str() {
+        "now" => return Ok(now.to_utc()),
+        "today" => return Ok(midnight(now.date_naive())?.to_utc()),
+        "yesterday" => {
+            let date = now.date_naive() - Duration::days(1);
+            return Ok(midnight(date)?.to_utc());
+        }
+        _ => (),
+    }
+
+    if let Ok(ts) = DateTime::parse_from_rfc3339(&spec) {
+        return Ok(ts.to_utc());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(&spec, "%Y-%m-%d") {
+        return Ok(midnight(date)?.to_utc());
+    }
+    for fmt in [
+        "%Y-%m-%dt%H:%M",
+        "%Y-%m-%dt%H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%d %H:%M:%S",
+    ] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(&spec, fmt) {
+            return Local
+                .from_local_datetime(&naive)
+                .earliest()
+                .map(|ts| ts.to_utc())
+                .ok_or_else(|| anyhow!("Invalid local time `{}`", spec));
+        }
+    }
+
+    if let Some(caps) = RELATIVE_RE.captures(&spec) {
+        let num: u32 = caps[1]
+            .parse()
+            .map_err(|_| anyhow!("Number too large in `{}`", spec))?;
+        let before = |duration: Option<Duration>| duration.and_then(|d| now.checked_sub_signed(d));
+        let ts = match &caps[2] {
+            "s" | "sec" | "secs" | "second" | "seconds" => {
+                before(Duration::try_seconds(num.into()))
+            }
+            "m" | "min" | "mins" | "minute" | "minutes" => {
+                before(Duration::try_minutes(num.into()))
+            }
+            "h" | "hr" | "hrs" | "hour" | "hours" => before(Duration::try_hours(num.into())),
+            "d" | "day" | "days" => before(Duration::try_days(num.into())),
+            "w" | "wk" | "wks" | "week" | "weeks" => before(Duration::try_weeks(num.into())),
+            "mo" | "month" | "months" => now.checked_sub_months(Months::new(num)),
+            "y" | "yr" | "yrs" | "year" | "years" => {
+                now.checked_sub_months(Months::new(num.saturating_mul(12)))
+            }
+            unit => return Err(anyhow!("Unknown time unit `{}` in `{}`", unit, spec)),
+        };
+        // The duration or the resulting time overflows
+        return ts
+            .map(|ts| ts.to_utc())
+            .ok_or_else(|| anyhow!("Number too large in `{}`", spec));
+    }
+
+    Err(anyhow!(
+        "Cannot parse `{}` as a time. Use, e.g., `2026-10-01`, `2026-10-01T14:00`, \
+         `3d`, `2 weeks ago`, or `yesterday`",
+        spec
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn local(s: &str) -> DateTime<Utc> {
+        let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        Local.from_local_datetime(&naive).unwrap().to_utc()
+    }
+
+    #[test]
+    fn test_parse_time_spec() {
+        let now = Local
+            .from_local_datetime(
+                &NaiveDateTime::parse_from_str("2026-10-16 13:14:15", "%Y-%m-%d %H:%M:%S").unwrap(),
+            )
+            .unwrap();
+        let parse = |s: &str| parse_time_spec(s, now).unwrap();
+
+        assert_eq!(parse("now"), now.to_utc());
+        assert_eq!(parse("today"), local("2026-10-16 00:00:00"));
+        assert_eq!(parse("Yesterday"), local("2026-10-15 00:00:00"));
+
+        assert_eq!(parse("2026-10-01"), local("2026-10-01 00:00:00"));
+        assert_eq!(parse("2026-10-01T14:00"), local("2026-10-01 14:00:00"));
+        assert_eq!(parse("2026-10-01 14:00:30"), local("2026-10-01 14:00:30"));
+        assert_eq!(
+            parse("2026-10-01T14:00:00+02:00"),
+            DateTime::parse_from_rfc3339("2026-10-01T12:00:00Z").unwrap()
+        );
+
+        assert_eq!(parse("90s"), now.to_utc() - Duration::seconds(90));
+        assert_eq!(parse("5m"), now.to_utc() - Duration::minutes(5));
+        assert_eq!(parse("3 hours ago"), now.to_utc() - Duration::hours(3));
+        assert_eq!(parse("3d"), now.to_utc() - Duration::days(3));
+        assert_eq!(parse("2 weeks ago"), now.to_utc() - Duration::weeks(2));
+        assert_eq!(parse("1mo"), local("2026-09-16 13:14:15"));
+        assert_eq!(parse("1 year ago"), local("2025-10-16 13:14:15"));
+
+        assert!(parse_time_spec("foo", now).is_err());
+        assert!(parse_time_spec("3 fortnights ago", now).is_err());
+        assert!(parse_time_spec("2026-13-01", now).is_err());
+        // Overflowing durations are errors, not panics
+        for too_large in ["99999999d", "4000000000h", "4000000000w"] {
+            let err = parse_time_spec(too_large, now).unwrap_err();
+            assert!(err.to_string().starts_with("Number too large"), "{}", err);
+        }
+        assert!(parse_time_spec("", now).is_err());
+    }
+
+    #[test]
+    fn test_time_range_contains() {
+        let t0 = default_ts() + Duration::days(100);
+        let mk = |ts| HistEntry::new(ts, "ls");
+        let range = TimeRange {
+            since: Some(t0),
+            until: Some(t0 + Duration::hours(1)),
+        };
+        assert!(range.contains(&mk(t0)));
+        assert!(range.contains(&mk(t0 + Duration::hours(1))));
+        assert!(!range.contains(&mk(t0 - Duration::seconds(1))));
+        assert!(!range.contains(&mk(t0 + Duration::minutes(61))));
+
+        let range = TimeRange {
+            since: None,
+            until: Some(t0),
+        };
+        assert!(range.contains(&mk(t0 - Duration::days(1))));
+        // Entries without timestamps are not in any bounded range
+        assert!(!range.contains(&mk(default_ts())));
+
+        // ... but they are in the unbounded one
+        assert!(TimeRange::default().contains(&mk(default_ts())));
+    }
+}